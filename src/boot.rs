@@ -0,0 +1,343 @@
+use crate::model::Model;
+use crate::registers::Registers;
+use std::{fmt, fs, io, path::Path};
+
+const DMG_BOOT_ROM_SIZE: usize = 0x100;
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+#[derive(Debug)]
+pub enum BootRomError {
+    Io(io::Error),
+    Size(usize),
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootRomError::Io(error) => write!(f, "{}", error),
+            BootRomError::Size(size) => write!(
+                f,
+                "boot ROM is {} bytes, expected {} (DMG) or {} (CGB)",
+                size, DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for BootRomError {
+    fn from(error: io::Error) -> Self {
+        BootRomError::Io(error)
+    }
+}
+
+/// A user supplied boot ROM, mapped over the start of the cartridge until 0xFF50 is written.
+pub struct BootRom {
+    data: Box<[u8]>,
+}
+
+impl BootRom {
+    pub fn new(data: Vec<u8>) -> Result<BootRom, BootRomError> {
+        match data.len() {
+            DMG_BOOT_ROM_SIZE | CGB_BOOT_ROM_SIZE => Ok(BootRom {
+                data: data.into_boxed_slice(),
+            }),
+            size => Err(BootRomError::Size(size)),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<BootRom, BootRomError> {
+        BootRom::new(fs::read(path)?)
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    // the CGB boot ROM leaves a hole so the cartridge header can be read through it
+    pub fn maps(&self, address: usize) -> bool {
+        address < 0x100 || (self.is_cgb() && (0x200..CGB_BOOT_ROM_SIZE).contains(&address))
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        self.data[address]
+    }
+}
+
+/// Registers as they are when a boot ROM starts running.
+pub fn power_on_registers() -> Registers {
+    Registers {
+        a: 0,
+        f: 0,
+        b: 0,
+        c: 0,
+        d: 0,
+        e: 0,
+        h: 0,
+        l: 0,
+        s: 0,
+        p: 0,
+        sp: 0,
+        pc: 0,
+    }
+}
+
+/// Registers as the boot ROM of `model` leaves them at 0x0100. Some of them depend on
+/// the header, so `rom` needs at least the first 0x0150 bytes of the cartridge.
+pub fn registers(model: Model, rom: &[u8]) -> Registers {
+    let header_checksum = rom[0x014D];
+    let cgb_cartridge = rom[0x0143] & 0x80 != 0;
+    // the DMG boot ROM's checksum loop leaves H and C set unless it ends on zero
+    let dmg_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+
+    let (a, f, b, c, d, e, h, l) = match model {
+        Model::DMG0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+        Model::DMG => (0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+        Model::MGB => (0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+        Model::SGB => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+        Model::SGB2 => (0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+        Model::CGB | Model::AGB if cgb_cartridge => {
+            (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D)
+        }
+        Model::CGB | Model::AGB => {
+            let checksum = title_checksum(rom);
+            // two games get their own compatibility palette lookup, which leaves HL elsewhere
+            let (h, l) = if checksum == 0x43 || checksum == 0x58 {
+                (0x99, 0x1A)
+            } else {
+                (0x00, 0x7C)
+            };
+            (0x11, 0x80, checksum, 0x00, 0x00, 0x08, h, l)
+        }
+    };
+
+    // the AGB boot ROM ends with an extra `inc b`, which is how games tell it apart
+    let (b, f) = if model == Model::AGB {
+        let b = b.wrapping_add(1);
+        let zero = if b == 0 { 0x80 } else { 0x00 };
+        let half_carry = if b & 0x0F == 0 { 0x20 } else { 0x00 };
+        (b, zero | half_carry)
+    } else {
+        (b, f)
+    };
+
+    Registers {
+        a,
+        f,
+        b,
+        c,
+        d,
+        e,
+        h,
+        l,
+        s: 0xFF,
+        p: 0xFE,
+        sp: 0xFFFE,
+        pc: 0x0100,
+    }
+}
+
+// what the CGB boot ROM uses to pick a palette for Nintendo published DMG games
+fn title_checksum(rom: &[u8]) -> u8 {
+    let nintendo = match rom[0x014B] {
+        0x01 => true,
+        0x33 => &rom[0x0144..0x0146] == b"01",
+        _ => false,
+    };
+    if !nintendo {
+        return 0;
+    }
+    rom[0x0134..=0x0143]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// I/O registers left behind by the boot ROM of `model`.
+pub fn io_registers(model: Model) -> Vec<(u16, u8)> {
+    let mut registers = vec![
+        (0xFF00, 0xCF), // P1
+        (0xFF02, 0x7E), // SC
+        (0xFF07, 0xF8), // TAC
+        (0xFF0F, 0xE1), // IF
+        (0xFF10, 0x80), // NR10
+        (0xFF11, 0xBF), // NR11
+        (0xFF12, 0xF3), // NR12
+        (0xFF13, 0xFF), // NR13
+        (0xFF14, 0xBF), // NR14
+        (0xFF16, 0x3F), // NR21
+        (0xFF17, 0x00), // NR22
+        (0xFF18, 0xFF), // NR23
+        (0xFF19, 0xBF), // NR24
+        (0xFF1A, 0x7F), // NR30
+        (0xFF1B, 0xFF), // NR31
+        (0xFF1C, 0x9F), // NR32
+        (0xFF1D, 0xFF), // NR33
+        (0xFF1E, 0xBF), // NR34
+        (0xFF20, 0xFF), // NR41
+        (0xFF21, 0x00), // NR42
+        (0xFF22, 0x00), // NR43
+        (0xFF23, 0xBF), // NR44
+        (0xFF24, 0x77), // NR50
+        (0xFF25, 0xF3), // NR51
+        (0xFF26, 0xF1), // NR52
+        (0xFF40, 0x91), // LCDC
+        (0xFF41, 0x85), // STAT
+        (0xFF42, 0x00), // SCY
+        (0xFF43, 0x00), // SCX
+        (0xFF45, 0x00), // LYC
+        (0xFF46, 0xFF), // DMA
+        (0xFF47, 0xFC), // BGP
+        (0xFF4A, 0x00), // WY
+        (0xFF4B, 0x00), // WX
+    ];
+
+    for (address, value) in &mut registers {
+        match (model, *address) {
+            // the SGB boot ROM hands sound over to the SNES, channel 1 isn't left playing
+            (Model::SGB | Model::SGB2, 0xFF26) => *value = 0xF0,
+            (Model::CGB | Model::AGB, 0xFF46) => *value = 0x00,
+            _ => {}
+        }
+    }
+    registers
+}
+
+/// The 16-bit internal divider at 0x0100, DIV being its upper byte. It's what games use
+/// to time the boot, so it differs with how long each boot ROM takes.
+pub fn divider(model: Model) -> u16 {
+    match model {
+        Model::DMG0 => 0x1830,
+        Model::DMG | Model::MGB => 0xABCC,
+        // depends on how long the SNES takes to answer, this is a typical boot
+        Model::SGB | Model::SGB2 => 0xD85C,
+        Model::CGB | Model::AGB => 0x2678,
+    }
+}
+
+// the (R) mark the DMG boot ROM draws after the logo
+const REGISTERED_TILE: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+/// VRAM writes the boot ROM leaves behind: the header logo scaled up into tiles 1-24, the
+/// (R) in tile 25, and on monochrome models the map entries that put them on screen.
+pub fn vram_contents(model: Model, logo: &[u8]) -> Vec<(u16, u8)> {
+    let mut writes = Vec::new();
+
+    // every nibble is a 4 pixel row, doubled in both directions
+    let mut address = 0x8010;
+    for byte in logo {
+        for nibble in [byte >> 4, byte & 0x0F] {
+            let row = (0..4).fold(0u8, |row, bit| {
+                if nibble & (0x08 >> bit) != 0 {
+                    row | (0xC0 >> (bit * 2))
+                } else {
+                    row
+                }
+            });
+            for _ in 0..2 {
+                writes.push((address, row));
+                writes.push((address + 1, 0x00));
+                address += 2;
+            }
+        }
+    }
+
+    for (i, row) in REGISTERED_TILE.iter().enumerate() {
+        writes.push((0x8190 + i as u16 * 2, *row));
+        writes.push((0x8190 + i as u16 * 2 + 1, 0x00));
+    }
+
+    // the CGB boot ROM clears the map when it fades out
+    if !model.is_cgb() {
+        for tile in 1..=12u8 {
+            writes.push((0x9903 + tile as u16, tile));
+            writes.push((0x9923 + tile as u16, tile + 12));
+        }
+        writes.push((0x9910, 0x19));
+    }
+    writes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0134..0x013A].copy_from_slice(b"TETRIS");
+        rom[0x014B] = 0x01;
+        rom[0x014D] = 0x0A;
+        rom
+    }
+
+    #[test]
+    fn test_boot_rom_size() {
+        assert!(BootRom::new(vec![0; 0x100]).is_ok());
+        assert!(matches!(
+            BootRom::new(vec![0; 0x200]),
+            Err(BootRomError::Size(0x200))
+        ));
+    }
+
+    #[test]
+    fn test_cgb_boot_rom_leaves_header_visible() {
+        let boot_rom = BootRom::new(vec![0; 0x900]).unwrap();
+        assert!(boot_rom.maps(0x00FF));
+        assert!(!boot_rom.maps(0x0100));
+        assert!(!boot_rom.maps(0x014F));
+        assert!(boot_rom.maps(0x0200));
+        assert!(boot_rom.maps(0x08FF));
+        assert!(!boot_rom.maps(0x0900));
+    }
+
+    #[test]
+    fn test_dmg_registers() {
+        let registers = registers(Model::DMG, &rom());
+        assert_eq!(registers.a, 0x01);
+        assert_eq!(registers.f, 0xB0);
+        assert_eq!(registers.pc, 0x0100);
+        assert_eq!(registers.sp, 0xFFFE);
+
+        let mut rom = rom();
+        rom[0x014D] = 0x00;
+        assert_eq!(super::registers(Model::DMG, &rom).f, 0x80);
+    }
+
+    #[test]
+    fn test_models_are_distinguishable() {
+        assert_eq!(registers(Model::MGB, &rom()).a, 0xFF);
+        assert_eq!(registers(Model::SGB, &rom()).c, 0x14);
+        assert_eq!(registers(Model::SGB2, &rom()).a, 0xFF);
+
+        let mut rom = rom();
+        rom[0x0143] = 0x80;
+        let cgb = registers(Model::CGB, &rom);
+        let agb = registers(Model::AGB, &rom);
+        assert_eq!(cgb.a, 0x11);
+        assert_eq!((cgb.b, cgb.f), (0x00, 0x80));
+        assert_eq!((agb.b, agb.f), (0x01, 0x00));
+    }
+
+    #[test]
+    fn test_cgb_dmg_cartridge_title_checksum() {
+        let registers = registers(Model::CGB, &rom());
+        let checksum = b"TETRIS"
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(registers.b, checksum);
+        assert_eq!((registers.h, registers.l), (0x00, 0x7C));
+    }
+
+    #[test]
+    fn test_logo_tiles() {
+        let logo = [0xCE, 0xED];
+        let writes = vram_contents(Model::DMG, &logo);
+        // 0xCE, top nibble 1100 becomes 11110000 on two rows
+        assert_eq!(
+            &writes[..4],
+            &[(0x8010, 0xF0), (0x8011, 0), (0x8012, 0xF0), (0x8013, 0)]
+        );
+        assert!(writes.contains(&(0x9904, 0x01)));
+        assert!(writes.contains(&(0x9910, 0x19)));
+        assert!(writes.contains(&(0x992F, 0x18)));
+        assert!(!vram_contents(Model::CGB, &logo).contains(&(0x9904, 0x01)));
+    }
+}
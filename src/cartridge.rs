@@ -1,3 +1,26 @@
+mod camera;
+mod gbx;
+mod header;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod save;
+mod tama5;
+mod unlicensed;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub use camera::{CameraSource, PocketCamera};
+pub use gbx::GbxFooter;
+pub(crate) use header::NINTENDO_LOGO;
+pub use header::{CartridgeInfo, HeaderError, Mapper};
+pub use mbc6::MBC6;
+pub use mbc7::MBC7;
+pub use mmm01::MMM01;
+pub use save::BatterySave;
+pub use tama5::TAMA5;
+pub use unlicensed::{detect_unlicensed, Bootleg, Sachen, WisdomTree};
+
 pub struct Catridge<T: MBC> {
     mbc: T,
 }
@@ -16,32 +39,370 @@ pub trait ReadableMemory {
     fn read(&self, address: usize) -> u8;
 }
 
-pub trait MBC: WritableMemory + ReadableMemory {}
+pub trait MBC: WritableMemory + ReadableMemory {
+    /// Advances hardware on the cartridge that runs off the system clock.
+    fn tick(&mut self, _ticks: u64) {}
+
+    /// Whether the cartridge is currently driving its rumble motor.
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Feeds the tilt of the console in g along the X and Y axes, for carts with an accelerometer.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    /// Whether the cartridge's infrared LED is lit.
+    fn infrared_led(&self) -> bool {
+        false
+    }
+
+    /// Tells the cartridge whether its infrared receiver is seeing light.
+    fn set_infrared_light(&mut self, _received: bool) {}
+
+    /// The tone a speaker on the cartridge was last asked to play, if it has one.
+    fn speaker_tone(&self) -> Option<u8> {
+        None
+    }
+
+    /// Where the Game Boy Camera gets its pictures from.
+    fn set_camera_source(&mut self, _source: CameraSource) {}
+
+    /// Battery-backed state to persist between sessions, empty when there is none.
+    fn save_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    /// Reports whether save data changed since the last call.
+    fn take_save_dirty(&mut self) -> bool {
+        false
+    }
+}
+
+impl<M: MBC + ?Sized> ReadableMemory for Box<M> {
+    fn read(&self, address: usize) -> u8 {
+        (**self).read(address)
+    }
+}
+
+impl<M: MBC + ?Sized> WritableMemory for Box<M> {
+    fn write(&mut self, address: usize, value: u8) {
+        (**self).write(address, value)
+    }
+}
+
+impl<M: MBC + ?Sized> MBC for Box<M> {
+    fn tick(&mut self, ticks: u64) {
+        (**self).tick(ticks)
+    }
+
+    fn rumble(&self) -> bool {
+        (**self).rumble()
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        (**self).set_tilt(x, y)
+    }
+
+    fn infrared_led(&self) -> bool {
+        (**self).infrared_led()
+    }
+
+    fn set_infrared_light(&mut self, received: bool) {
+        (**self).set_infrared_light(received)
+    }
+
+    fn speaker_tone(&self) -> Option<u8> {
+        (**self).speaker_tone()
+    }
+
+    fn set_camera_source(&mut self, source: CameraSource) {
+        (**self).set_camera_source(source)
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        (**self).save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        (**self).load_save_data(data)
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        (**self).take_save_dirty()
+    }
+}
+
+/// Splits a ROM image into 16KB banks, padding the last one if the dump is short.
+pub fn rom_banks(rom: &[u8]) -> Box<[Box<[u8; 0x4000]>]> {
+    rom.chunks(0x4000)
+        .map(|c| {
+            let mut sized_array = [0xFFu8; 0x4000];
+            sized_array[..c.len()].copy_from_slice(c);
+            Box::new(sized_array)
+        })
+        .collect::<Vec<_>>()
+        .into_boxed_slice()
+}
+
+/// Picks a mapper for the ROM based on the cartridge type byte at 0x0147.
+pub fn from_rom(rom: &[u8]) -> Result<Box<dyn MBC>, HeaderError> {
+    Ok(from_info(&CartridgeInfo::from_header(rom)?, rom))
+}
+
+pub fn from_info(info: &CartridgeInfo, rom: &[u8]) -> Box<dyn MBC> {
+    // a trimmed dump gets its missing banks back, so bank numbers wrap at the real size
+    let rom_banks = match info.rom_size {
+        Some(rom_size) if rom_size > rom.len() => {
+            let mut padded = rom.to_vec();
+            padded.resize(rom_size, 0xFF);
+            rom_banks(&padded)
+        }
+        _ => rom_banks(rom),
+    };
+
+    match info.mapper {
+        Mapper::RomOnly => Box::new(MBC0::new(rom_banks)),
+        Mapper::MBC1 => Box::new(MBC1::new(rom_banks, info.ram_size)),
+        Mapper::MBC1M => Box::new(MBC1::new_multicart(rom_banks, info.ram_size)),
+        Mapper::MBC3 => Box::new(MBC3::new(rom_banks, info.ram_size)),
+        Mapper::MBC5 => Box::new(MBC5::new(rom_banks, info.ram_size, info.has_rumble)),
+        Mapper::MMM01 => Box::new(MMM01::new(rom_banks, info.ram_size)),
+        Mapper::MBC6 => Box::new(MBC6::new(rom_banks)),
+        Mapper::MBC7 => Box::new(MBC7::new(rom_banks)),
+        Mapper::PocketCamera => Box::new(PocketCamera::new(rom_banks)),
+        Mapper::TAMA5 => Box::new(TAMA5::new(rom_banks)),
+        Mapper::HuC1 => Box::new(HuC1::new(rom_banks, info.ram_size)),
+        Mapper::HuC3 => Box::new(HuC3::new(rom_banks, info.ram_size)),
+        Mapper::WisdomTree => Box::new(WisdomTree::new(rom_banks)),
+        Mapper::SachenMMC1 => Box::new(Sachen::new_mmc1(rom_banks)),
+        Mapper::SachenMMC2 => Box::new(Sachen::new_mmc2(rom_banks)),
+        Mapper::BootlegMBC1(bank_register) => Box::new(Bootleg::new(
+            MBC1::new(rom_banks, info.ram_size),
+            bank_register,
+        )),
+        Mapper::BootlegMBC5(bank_register) => Box::new(Bootleg::new(
+            MBC5::new(rom_banks, info.ram_size, info.has_rumble),
+            bank_register,
+        )),
+        // MBC2 and unknown types have no mapper of their own yet, MBC3 banking gets most
+        // of them to boot
+        Mapper::MBC2 | Mapper::Unknown(_) => Box::new(MBC3::new(rom_banks, info.ram_size)),
+    }
+}
+
+// battery saves are a straight dump of the RAM chip, like most emulators write them
+pub(crate) fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let length = ram.len().min(data.len());
+    ram[..length].copy_from_slice(&data[..length]);
+}
 
+// RAM offset for a bank select, masked by the number of banks actually present
+pub(crate) fn ram_offset(ram: &[u8], ram_bank_select: u8, address: usize) -> usize {
+    let ram_bank_count = (ram.len() / 0x2000).max(1);
+    let ram_bank_select = ram_bank_select as usize % ram_bank_count;
+    (ram_bank_select * 0x2000 + (address & 0x1FFF)) % ram.len()
+}
+
+/// No mapper at all, 32KB of ROM wired straight to the bus.
 pub struct MBC0 {
-    rom: [u8; 0x8000],
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
 }
 
 pub struct MBC1 {
-    rom_banks: [[u8; 0x2000]; 0xFFFFF],
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    ram_enabled: bool,
+    // the 5 bit register at 0x2000 and the 2 bit one at 0x4000
+    bank1: u8,
+    bank2: u8,
+    // 1 lets bank2 reach the 0x0000 area and RAM, 0 keeps both at bank 0
+    mode: u8,
+    // multicarts wire bank2 one bit lower and leave the top bit of bank1 unconnected
+    bank2_shift: u8,
+    ram: Box<[u8]>,
+    ram_dirty: bool,
 }
 
 pub struct MBC3 {
     rom_bank_select: u8,
     rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    ram_enabled: bool,
     ram_bank_select: u8,
-    ram_banks: Box<[[u8; 0x2000]; 0x04]>,
+    ram: Box<[u8]>,
+    ram_dirty: bool,
+}
+
+/// Size in bytes of the external RAM described by the header byte at 0x0149.
+pub fn ram_size(code: u8) -> usize {
+    match code {
+        0x01 => 0x800, // unofficial 2KB, only seen on a handful of carts
+        0x02 => 0x2000,
+        0x03 => 0x8000,
+        0x04 => 0x20000,
+        0x05 => 0x10000,
+        _ => 0,
+    }
+}
+
+impl MBC0 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> MBC0 {
+        MBC0 { rom_banks }
+    }
+}
+
+impl ReadableMemory for MBC0 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x7FFF => {
+                let bank = (address / 0x4000) % self.rom_banks.len();
+                self.rom_banks[bank][address & 0x3FFF]
+            }
+            // no RAM on the cart
+            0xA000..=0xBFFF => 0xFF,
+            _ => {
+                panic!("invalid address to read in MBC0, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for MBC0 {
+    fn write(&mut self, address: usize, _value: u8) {
+        match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => {}
+            _ => {
+                panic!("invalid address to write to MBC0");
+            }
+        }
+    }
+}
+
+impl MBC for MBC0 {}
+
+impl MBC1 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>, ram_size: usize) -> MBC1 {
+        MBC1 {
+            rom_banks,
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: 0,
+            bank2_shift: 5,
+            ram: vec![0u8; ram_size].into_boxed_slice(),
+            ram_dirty: false,
+        }
+    }
+
+    /// MBC1M, several 256KB games on one cart, each seeing banks 0x00-0x0F.
+    pub fn new_multicart(rom_banks: Box<[Box<[u8; 0x4000]>]>, ram_size: usize) -> MBC1 {
+        MBC1 {
+            bank2_shift: 4,
+            ..MBC1::new(rom_banks, ram_size)
+        }
+    }
+
+    fn ram_bank(&self) -> u8 {
+        if self.mode == 1 {
+            self.bank2
+        } else {
+            0
+        }
+    }
+}
+
+impl ReadableMemory for MBC1 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x3FFF => {
+                let bank = if self.mode == 1 {
+                    (self.bank2 as usize) << self.bank2_shift
+                } else {
+                    0
+                };
+                self.rom_banks[bank % self.rom_banks.len()][address]
+            }
+            0x4000..=0x7FFF => {
+                let bank1 = self.bank1 as usize & ((1 << self.bank2_shift) - 1);
+                let bank = (self.bank2 as usize) << self.bank2_shift | bank1;
+                self.rom_banks[bank % self.rom_banks.len()][address & 0x3FFF]
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return 0xFF;
+                }
+                self.ram[ram_offset(&self.ram, self.ram_bank(), address)]
+            }
+            _ => {
+                panic!("invalid address to read in MBC1, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for MBC1 {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+            }
+            0x2000..=0x3FFF => {
+                // only the 5 bit register is checked for 0, so banks 0x20/0x40/0x60 can't
+                // be reached through 0x4000 either
+                self.bank1 = (value & 0x1F).max(1);
+            }
+            0x4000..=0x5FFF => {
+                self.bank2 = value & 0x03;
+            }
+            0x6000..=0x7FFF => {
+                self.mode = value & 0x01;
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return;
+                }
+                let physical_address = ram_offset(&self.ram, self.ram_bank(), address);
+                self.ram[physical_address] = value;
+                self.ram_dirty = true;
+            }
+            _ => {
+                panic!("invalid address to write to MBC1");
+            }
+        }
+    }
+}
+
+impl MBC for MBC1 {
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
 }
 
 impl MBC3 {
-    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> MBC3 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>, ram_size: usize) -> MBC3 {
         MBC3 {
             rom_bank_select: 1,
             rom_banks,
+            ram_enabled: false,
             ram_bank_select: 0,
-            ram_banks: Box::new([[0u8; 0x2000]; 0x04]),
+            ram: vec![0u8; ram_size].into_boxed_slice(),
+            ram_dirty: false,
         }
     }
+
+    // MBC30 is an MBC3 with a wider ROM bank register and twice the RAM banks;
+    // it only ever shows up on carts that need the extra space
+    fn is_mbc30(&self) -> bool {
+        self.rom_banks.len() > 0x80 || self.ram.len() > 0x8000
+    }
 }
 
 impl ReadableMemory for MBC3 {
@@ -49,14 +410,15 @@ impl ReadableMemory for MBC3 {
         match address {
             0x0000..=0x3FFF => self.rom_banks[0][address],
             0x4000..=0x7FFF => {
-                let bank_select = self.rom_bank_select as usize;
+                let bank_select = self.rom_bank_select as usize % self.rom_banks.len();
                 let physical_address = address & 0x3FFF;
                 return self.rom_banks[bank_select][physical_address];
             }
             0xA000..=0xBFFF => {
-                let ram_bank_select = self.ram_bank_select as usize;
-                let physical_address = address & 0x1FFF;
-                return self.ram_banks[ram_bank_select][physical_address];
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return 0xFF;
+                }
+                self.ram[ram_offset(&self.ram, self.ram_bank_select, address)]
             }
             _ => {
                 panic!("invalid address to read in MBC3, this must be a programming error");
@@ -69,20 +431,21 @@ impl WritableMemory for MBC3 {
     fn write(&mut self, address: usize, value: u8) {
         match address {
             0x0000..=0x1FFF => {
-                // TODO: write protect RAM here, somehow
+                self.ram_enabled = value & 0x0F == 0x0A;
             }
             0x2000..=0x3FFF => {
-                let rom_bank = value & 0x7F;
-                if rom_bank == 0 {
-                    panic!("selecting this ROM bank in MBC3 is undefined");
-                }
-                self.rom_bank_select = rom_bank;
+                let rom_bank = if self.is_mbc30() { value } else { value & 0x7F };
+                // bank 0 can't be mapped to the upper region, the MBC maps it to bank 1 instead
+                self.rom_bank_select = rom_bank.max(1);
             }
             0x4000..=0x5FFF => {
                 match value {
                     0x00..=0x03 => {
                         self.ram_bank_select = value;
                     }
+                    0x04..=0x07 if self.is_mbc30() => {
+                        self.ram_bank_select = value;
+                    }
                     0x08..=0x0C => {
                         // TODO: handle counters
                     }
@@ -95,9 +458,12 @@ impl WritableMemory for MBC3 {
                 // writing this region is not defined in the manual and will behave as noop
             }
             0xA000..=0xBFFF => {
-                let ram_bank_select = self.ram_bank_select as usize;
-                let physical_address = address & 0x1FFF;
-                self.ram_banks[ram_bank_select][physical_address] = value;
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return;
+                }
+                let physical_address = ram_offset(&self.ram, self.ram_bank_select, address);
+                self.ram[physical_address] = value;
+                self.ram_dirty = true;
             }
             _ => {
                 // if this happens it must be a code error because the MMU should do something else
@@ -107,4 +473,745 @@ impl WritableMemory for MBC3 {
     }
 }
 
-impl MBC for MBC3 {}
+impl MBC for MBC3 {
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+}
+
+pub struct MBC5 {
+    rom_bank_select: u16,
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    ram_enabled: bool,
+    ram_bank_select: u8,
+    ram: Box<[u8]>,
+    ram_dirty: bool,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>, ram_size: usize, has_rumble: bool) -> MBC5 {
+        MBC5 {
+            rom_bank_select: 1,
+            rom_banks,
+            ram_enabled: false,
+            ram_bank_select: 0,
+            ram: vec![0u8; ram_size].into_boxed_slice(),
+            ram_dirty: false,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl ReadableMemory for MBC5 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.rom_banks[0][address],
+            0x4000..=0x7FFF => {
+                let bank_select = self.rom_bank_select as usize % self.rom_banks.len();
+                self.rom_banks[bank_select][address & 0x3FFF]
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return 0xFF;
+                }
+                self.ram[ram_offset(&self.ram, self.ram_bank_select, address)]
+            }
+            _ => {
+                panic!("invalid address to read in MBC5, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for MBC5 {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                // unlike MBC1/3, MBC5 checks the whole byte
+                self.ram_enabled = value == 0x0A;
+            }
+            0x2000..=0x2FFF => {
+                self.rom_bank_select = (self.rom_bank_select & 0x100) | value as u16;
+            }
+            0x3000..=0x3FFF => {
+                self.rom_bank_select = (self.rom_bank_select & 0xFF) | ((value as u16 & 0x01) << 8);
+            }
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    // bit 3 is wired to the motor instead of the RAM chip
+                    self.rumble = value & 0x08 == 0x08;
+                    self.ram_bank_select = value & 0x07;
+                } else {
+                    self.ram_bank_select = value & 0x0F;
+                }
+            }
+            0x6000..=0x7FFF => {
+                // no registers here on MBC5
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return;
+                }
+                let physical_address = ram_offset(&self.ram, self.ram_bank_select, address);
+                self.ram[physical_address] = value;
+                self.ram_dirty = true;
+            }
+            _ => {
+                panic!("invalid address to write to MBC5");
+            }
+        }
+    }
+}
+
+impl MBC for MBC5 {
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+}
+
+pub struct HuC1 {
+    rom_bank_select: u8,
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    infrared_mode: bool,
+    ram_bank_select: u8,
+    ram: Box<[u8]>,
+    ram_dirty: bool,
+    infrared_led: bool,
+    infrared_light: bool,
+}
+
+impl HuC1 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>, ram_size: usize) -> HuC1 {
+        HuC1 {
+            rom_bank_select: 1,
+            rom_banks,
+            infrared_mode: false,
+            ram_bank_select: 0,
+            ram: vec![0u8; ram_size].into_boxed_slice(),
+            ram_dirty: false,
+            infrared_led: false,
+            infrared_light: false,
+        }
+    }
+}
+
+impl ReadableMemory for HuC1 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.rom_banks[0][address],
+            0x4000..=0x7FFF => {
+                let bank_select = self.rom_bank_select as usize % self.rom_banks.len();
+                self.rom_banks[bank_select][address & 0x3FFF]
+            }
+            0xA000..=0xBFFF => {
+                if self.infrared_mode {
+                    // bit 0 is set while the receiver sees light
+                    return 0xC0 | self.infrared_light as u8;
+                }
+                if self.ram.is_empty() {
+                    return 0xFF;
+                }
+                self.ram[ram_offset(&self.ram, self.ram_bank_select, address)]
+            }
+            _ => {
+                panic!("invalid address to read in HuC1, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for HuC1 {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                // there's no RAM enable, this only switches the window between RAM and IR
+                self.infrared_mode = value & 0x0F == 0x0E;
+            }
+            0x2000..=0x3FFF => {
+                self.rom_bank_select = value & 0x3F;
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_select = value & 0x03;
+            }
+            0x6000..=0x7FFF => {}
+            0xA000..=0xBFFF => {
+                if self.infrared_mode {
+                    self.infrared_led = value & 0x01 == 0x01;
+                    return;
+                }
+                if self.ram.is_empty() {
+                    return;
+                }
+                let physical_address = ram_offset(&self.ram, self.ram_bank_select, address);
+                self.ram[physical_address] = value;
+                self.ram_dirty = true;
+            }
+            _ => {
+                panic!("invalid address to write to HuC1");
+            }
+        }
+    }
+}
+
+impl MBC for HuC1 {
+    fn infrared_led(&self) -> bool {
+        self.infrared_led
+    }
+
+    fn set_infrared_light(&mut self, received: bool) {
+        self.infrared_light = received;
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+}
+
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub struct HuC3 {
+    rom_bank_select: u8,
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    mode: u8,
+    ram_bank_select: u8,
+    ram: Box<[u8]>,
+    ram_dirty: bool,
+    // the clock runs off wall time, this is the unix time at which it read day 0 00:00
+    rtc_epoch: u64,
+    rtc_memory: [u8; 0x100],
+    rtc_address: u8,
+    rtc_response: u8,
+    tone: Option<u8>,
+    infrared_led: bool,
+    infrared_light: bool,
+    clock: fn() -> u64,
+}
+
+impl HuC3 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>, ram_size: usize) -> HuC3 {
+        let clock: fn() -> u64 = unix_time;
+        HuC3 {
+            rom_bank_select: 1,
+            rom_banks,
+            mode: 0,
+            ram_bank_select: 0,
+            ram: vec![0u8; ram_size].into_boxed_slice(),
+            ram_dirty: false,
+            rtc_epoch: clock(),
+            rtc_memory: [0u8; 0x100],
+            rtc_address: 0,
+            rtc_response: 0,
+            tone: None,
+            infrared_led: false,
+            infrared_light: false,
+            clock,
+        }
+    }
+
+    // minutes since midnight and days, as the 12 bit counters the cart keeps
+    fn rtc_time(&self) -> (u16, u16) {
+        let minutes = (self.clock)().saturating_sub(self.rtc_epoch) / 60;
+        ((minutes % 1440) as u16, ((minutes / 1440) & 0xFFF) as u16)
+    }
+
+    fn set_rtc_time(&mut self, minutes: u16, days: u16) {
+        let seconds = (days as u64 * 1440 + minutes as u64) * 60;
+        self.rtc_epoch = (self.clock)().saturating_sub(seconds);
+        self.ram_dirty = true;
+    }
+
+    fn rtc_command(&mut self, value: u8) {
+        let command = (value >> 4) & 0x07;
+        let argument = value & 0x0F;
+        match command {
+            0x01 => {
+                let value = self.rtc_memory[self.rtc_address as usize] & 0x0F;
+                self.rtc_response = (command << 4) | value;
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            }
+            0x03 => {
+                self.rtc_memory[self.rtc_address as usize] = argument;
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            }
+            0x04 => self.rtc_address = (self.rtc_address & 0xF0) | argument,
+            0x05 => self.rtc_address = (self.rtc_address & 0x0F) | (argument << 4),
+            0x06 => match argument {
+                0x00 => {
+                    // latch the clock into 0x00..=0x05, a nibble at a time
+                    let (minutes, days) = self.rtc_time();
+                    for i in 0..3 {
+                        self.rtc_memory[i] = ((minutes >> (i * 4)) & 0x0F) as u8;
+                        self.rtc_memory[i + 3] = ((days >> (i * 4)) & 0x0F) as u8;
+                    }
+                }
+                0x01 => {
+                    let nibbles = |offset: usize| {
+                        (0..3).fold(0u16, |value, i| {
+                            value | ((self.rtc_memory[offset + i] as u16 & 0x0F) << (i * 4))
+                        })
+                    };
+                    let (minutes, days) = (nibbles(0), nibbles(3));
+                    self.set_rtc_time(minutes % 1440, days);
+                }
+                0x02 => {
+                    // status check, the cart always answers that it's ready
+                    self.rtc_response = (command << 4) | 0x01;
+                }
+                0x0E => {
+                    self.tone = Some(self.rtc_memory[0x27] & 0x0F);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl ReadableMemory for HuC3 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.rom_banks[0][address],
+            0x4000..=0x7FFF => {
+                let bank_select = self.rom_bank_select as usize % self.rom_banks.len();
+                self.rom_banks[bank_select][address & 0x3FFF]
+            }
+            0xA000..=0xBFFF => match self.mode {
+                0x00 | 0x0A if !self.ram.is_empty() => {
+                    self.ram[ram_offset(&self.ram, self.ram_bank_select, address)]
+                }
+                0x0C => self.rtc_response,
+                // semaphore, commands finish instantly so it always reads ready
+                0x0D => 0x01,
+                0x0E => 0xC0 | self.infrared_light as u8,
+                _ => 0xFF,
+            },
+            _ => {
+                panic!("invalid address to read in HuC3, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for HuC3 {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.mode = value & 0x0F;
+            }
+            0x2000..=0x3FFF => {
+                self.rom_bank_select = value & 0x7F;
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_select = value & 0x03;
+            }
+            0x6000..=0x7FFF => {}
+            0xA000..=0xBFFF => match self.mode {
+                0x0A if !self.ram.is_empty() => {
+                    let physical_address = ram_offset(&self.ram, self.ram_bank_select, address);
+                    self.ram[physical_address] = value;
+                    self.ram_dirty = true;
+                }
+                0x0B => self.rtc_command(value),
+                0x0E => self.infrared_led = value & 0x01 == 0x01,
+                _ => {}
+            },
+            _ => {
+                panic!("invalid address to write to HuC3");
+            }
+        }
+    }
+}
+
+impl MBC for HuC3 {
+    fn infrared_led(&self) -> bool {
+        self.infrared_led
+    }
+
+    fn set_infrared_light(&mut self, received: bool) {
+        self.infrared_light = received;
+    }
+
+    fn speaker_tone(&self) -> Option<u8> {
+        self.tone
+    }
+
+    // RAM followed by the clock epoch, so the clock keeps running while the game is off
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.to_vec();
+        data.extend_from_slice(&self.rtc_epoch.to_le_bytes());
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        if let Some(epoch) = data.get(self.ram.len()..self.ram.len() + 8) {
+            self.rtc_epoch = u64::from_le_bytes(epoch.try_into().unwrap());
+        }
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered_rom(count: usize) -> Vec<u8> {
+        let mut rom = vec![0u8; count * 0x4000];
+        for i in 0..count {
+            rom[i * 0x4000] = i as u8;
+            rom[i * 0x4000 + 1] = (i >> 8) as u8;
+        }
+        rom
+    }
+
+    fn numbered_banks(count: usize) -> Box<[Box<[u8; 0x4000]>]> {
+        rom_banks(&numbered_rom(count))
+    }
+
+    fn read_bank_number<T: MBC>(mbc: &T) -> usize {
+        mbc.read(0x4000) as usize | (mbc.read(0x4001) as usize) << 8
+    }
+
+    #[test]
+    fn test_mbc1_banking() {
+        let mut mbc1 = MBC1::new(numbered_banks(0x80), 0x8000);
+        assert_eq!(read_bank_number(&mbc1), 1);
+        mbc1.write(0x2000, 0x00);
+        assert_eq!(read_bank_number(&mbc1), 1);
+
+        // bank 0x20 isn't reachable, writing 0 to the low register still gives 0x21
+        mbc1.write(0x4000, 0x01);
+        assert_eq!(read_bank_number(&mbc1), 0x21);
+        mbc1.write(0x2000, 0x05);
+        assert_eq!(read_bank_number(&mbc1), 0x25);
+
+        // mode 1 also moves the 0x0000 area
+        assert_eq!(mbc1.read(0x0000), 0x00);
+        mbc1.write(0x6000, 0x01);
+        assert_eq!(mbc1.read(0x0000), 0x20);
+    }
+
+    #[test]
+    fn test_mbc1_multicart_banking() {
+        let mut mbc1 = MBC1::new_multicart(numbered_banks(0x40), 0);
+        // the top bit of bank1 isn't wired, so 0x12 only reaches bank 2
+        mbc1.write(0x2000, 0x12);
+        assert_eq!(read_bank_number(&mbc1), 0x02);
+        mbc1.write(0x4000, 0x01);
+        assert_eq!(read_bank_number(&mbc1), 0x12);
+
+        // each game's bank 0 is reachable from 0x0000 in mode 1
+        mbc1.write(0x6000, 0x01);
+        assert_eq!(mbc1.read(0x0000), 0x10);
+        mbc1.write(0x4000, 0x03);
+        assert_eq!(mbc1.read(0x0000), 0x30);
+    }
+
+    #[test]
+    fn test_mbc1_ram_banking_needs_mode_1() {
+        let mut mbc1 = MBC1::new(numbered_banks(4), 0x8000);
+        mbc1.write(0x0000, 0x0A);
+        mbc1.write(0x4000, 0x02);
+        mbc1.write(0xA000, 0x11);
+        mbc1.write(0x6000, 0x01);
+        assert_eq!(mbc1.read(0xA000), 0x00);
+        mbc1.write(0xA000, 0x22);
+
+        mbc1.write(0x6000, 0x00);
+        assert_eq!(mbc1.read(0xA000), 0x11);
+        assert!(mbc1.take_save_dirty());
+        assert_eq!(mbc1.save_data()[0x4000], 0x22);
+    }
+
+    #[test]
+    fn test_mbc0_ignores_writes() {
+        let mut mbc0 = MBC0::new(numbered_banks(2));
+        mbc0.write(0x2000, 0x00);
+        assert_eq!(read_bank_number(&mbc0), 1);
+        assert_eq!(mbc0.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc3_ram_disabled_by_default() {
+        let mut mbc3 = MBC3::new(numbered_banks(4), 0x2000);
+        mbc3.write(0xA000, 0x69);
+        assert_eq!(mbc3.read(0xA000), 0xFF);
+
+        mbc3.write(0x0000, 0x0A);
+        assert_eq!(mbc3.read(0xA000), 0x00);
+    }
+
+    #[test]
+    fn test_mbc3_ram_enable_pattern() {
+        let mut mbc3 = MBC3::new(numbered_banks(4), 0x2000);
+        mbc3.write(0x1FFF, 0xFA);
+        mbc3.write(0xA123, 0x69);
+        assert_eq!(mbc3.read(0xA123), 0x69);
+
+        mbc3.write(0x0000, 0x00);
+        assert_eq!(mbc3.read(0xA123), 0xFF);
+        mbc3.write(0xA123, 0x42);
+
+        mbc3.write(0x0000, 0x0A);
+        assert_eq!(mbc3.read(0xA123), 0x69);
+    }
+
+    #[test]
+    fn test_mbc3_without_ram() {
+        let mut mbc3 = MBC3::new(numbered_banks(2), ram_size(0x00));
+        mbc3.write(0x0000, 0x0A);
+        mbc3.write(0xA000, 0x69);
+        assert_eq!(mbc3.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc3_ram_banks_masked_by_size() {
+        let mut mbc3 = MBC3::new(numbered_banks(2), ram_size(0x02));
+        mbc3.write(0x0000, 0x0A);
+        mbc3.write(0xA000, 0x69);
+        mbc3.write(0x4000, 0x03);
+        assert_eq!(mbc3.read(0xA000), 0x69);
+    }
+
+    #[test]
+    fn test_mbc3_rom_bank_masked_by_count() {
+        let mut mbc3 = MBC3::new(numbered_banks(4), 0);
+        mbc3.write(0x2000, 0x06);
+        assert_eq!(mbc3.read(0x4000), 2);
+
+        mbc3.write(0x2000, 0x00);
+        assert_eq!(mbc3.read(0x4000), 1);
+    }
+
+    #[test]
+    fn test_mbc30_banking() {
+        let mut mbc30 = MBC3::new(numbered_banks(0x100), ram_size(0x05));
+        mbc30.write(0x2000, 0xC5);
+        assert_eq!(mbc30.read(0x4000), 0xC5);
+
+        mbc30.write(0x0000, 0x0A);
+        mbc30.write(0x4000, 0x07);
+        mbc30.write(0xA000, 0x69);
+        mbc30.write(0x4000, 0x03);
+        assert_eq!(mbc30.read(0xA000), 0x00);
+        mbc30.write(0x4000, 0x07);
+        assert_eq!(mbc30.read(0xA000), 0x69);
+    }
+
+    #[test]
+    fn test_mbc5_nine_bit_rom_bank() {
+        let mut mbc5 = MBC5::new(numbered_banks(0x200), 0, false);
+        assert_eq!(read_bank_number(&mbc5), 1);
+
+        mbc5.write(0x2000, 0x23);
+        mbc5.write(0x3000, 0x01);
+        assert_eq!(read_bank_number(&mbc5), 0x123);
+
+        mbc5.write(0x2000, 0x45);
+        assert_eq!(read_bank_number(&mbc5), 0x145);
+
+        mbc5.write(0x3000, 0x00);
+        assert_eq!(read_bank_number(&mbc5), 0x45);
+    }
+
+    #[test]
+    fn test_mbc5_bank_zero_in_upper_region() {
+        let mut mbc5 = MBC5::new(numbered_banks(4), 0, false);
+        mbc5.write(0x2000, 0x00);
+        assert_eq!(read_bank_number(&mbc5), 0);
+    }
+
+    #[test]
+    fn test_mbc5_ram_banks() {
+        let mut mbc5 = MBC5::new(numbered_banks(2), ram_size(0x04), false);
+        mbc5.write(0x0000, 0x0A);
+        mbc5.write(0x4000, 0x0F);
+        mbc5.write(0xA000, 0x69);
+        mbc5.write(0x4000, 0x00);
+        assert_eq!(mbc5.read(0xA000), 0x00);
+        mbc5.write(0x4000, 0x0F);
+        assert_eq!(mbc5.read(0xA000), 0x69);
+
+        mbc5.write(0x0000, 0x1A);
+        assert_eq!(mbc5.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc5_rumble() {
+        let mut mbc5 = MBC5::new(numbered_banks(2), ram_size(0x03), true);
+        mbc5.write(0x0000, 0x0A);
+        mbc5.write(0x4000, 0x09);
+        assert!(mbc5.rumble());
+        mbc5.write(0xA000, 0x69);
+
+        mbc5.write(0x4000, 0x01);
+        assert!(!mbc5.rumble());
+        assert_eq!(mbc5.read(0xA000), 0x69);
+    }
+
+    #[test]
+    fn test_from_rom_selects_mbc5() {
+        let mut rom = numbered_rom(4);
+        rom[0x0147] = 0x1C;
+        rom[0x0149] = 0x03;
+        let mut mbc = from_rom(&rom).unwrap();
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4000), 0);
+        mbc.write(0x4000, 0x08);
+        assert!(mbc.rumble());
+    }
+
+    #[test]
+    fn test_huc1_infrared_mode() {
+        let mut huc1 = HuC1::new(numbered_banks(4), ram_size(0x03));
+        huc1.write(0xA000, 0x69);
+        assert_eq!(huc1.read(0xA000), 0x69);
+
+        huc1.write(0x0000, 0x0E);
+        assert_eq!(huc1.read(0xA000), 0xC0);
+        huc1.set_infrared_light(true);
+        assert_eq!(huc1.read(0xA000), 0xC1);
+        huc1.write(0xA000, 0x01);
+        assert!(huc1.infrared_led());
+
+        huc1.write(0x0000, 0x00);
+        assert_eq!(huc1.read(0xA000), 0x69);
+    }
+
+    #[test]
+    fn test_huc1_banking() {
+        let mut huc1 = HuC1::new(numbered_banks(0x40), ram_size(0x03));
+        huc1.write(0x2000, 0x3F);
+        assert_eq!(read_bank_number(&huc1), 0x3F);
+
+        huc1.write(0x4000, 0x02);
+        huc1.write(0xA000, 0x69);
+        huc1.write(0x4000, 0x00);
+        assert_eq!(huc1.read(0xA000), 0x00);
+    }
+
+    fn huc3(now: fn() -> u64) -> HuC3 {
+        HuC3 {
+            clock: now,
+            rtc_epoch: now(),
+            ..HuC3::new(numbered_banks(4), ram_size(0x03))
+        }
+    }
+
+    fn rtc_command(huc3: &mut HuC3, command: u8) -> u8 {
+        huc3.write(0x0000, 0x0B);
+        huc3.write(0xA000, command);
+        huc3.write(0x0000, 0x0C);
+        huc3.read(0xA000)
+    }
+
+    #[test]
+    fn test_huc3_ram_modes() {
+        let mut huc3 = huc3(|| 0);
+        huc3.write(0xA000, 0x69);
+        assert_eq!(huc3.read(0xA000), 0x00);
+
+        huc3.write(0x0000, 0x0A);
+        huc3.write(0xA000, 0x69);
+        huc3.write(0x0000, 0x00);
+        assert_eq!(huc3.read(0xA000), 0x69);
+    }
+
+    #[test]
+    fn test_huc3_rtc_latch_and_read() {
+        // day 2, 01:05
+        let mut huc3 = huc3(|| (2 * 1440 + 65) * 60);
+        huc3.rtc_epoch = 0;
+        rtc_command(&mut huc3, 0x60);
+        rtc_command(&mut huc3, 0x40);
+        rtc_command(&mut huc3, 0x50);
+
+        let nibbles: Vec<u8> = (0..6)
+            .map(|_| rtc_command(&mut huc3, 0x10) & 0x0F)
+            .collect();
+        assert_eq!(nibbles, [0x1, 0x4, 0x0, 0x2, 0x0, 0x0]);
+    }
+
+    #[test]
+    fn test_huc3_rtc_set_and_persist() {
+        let mut huc3 = huc3(|| 1_000_000);
+        rtc_command(&mut huc3, 0x40);
+        rtc_command(&mut huc3, 0x50);
+        // 0x00A minutes, 0x003 days
+        for nibble in [0xA, 0x0, 0x0, 0x3, 0x0, 0x0] {
+            rtc_command(&mut huc3, 0x30 | nibble);
+        }
+        rtc_command(&mut huc3, 0x61);
+        assert!(huc3.take_save_dirty());
+        assert_eq!(huc3.rtc_time(), (10, 3));
+
+        let mut loaded = self::huc3(|| 1_000_000 + 3600);
+        loaded.load_save_data(&huc3.save_data());
+        assert_eq!(loaded.rtc_time(), (70, 3));
+    }
+
+    #[test]
+    fn test_huc3_semaphore_and_infrared() {
+        let mut huc3 = huc3(|| 0);
+        huc3.write(0x0000, 0x0D);
+        assert_eq!(huc3.read(0xA000), 0x01);
+
+        huc3.write(0x0000, 0x0E);
+        huc3.set_infrared_light(true);
+        assert_eq!(huc3.read(0xA000), 0xC1);
+        huc3.write(0xA000, 0x01);
+        assert!(huc3.infrared_led());
+    }
+
+    #[test]
+    fn test_huc3_speaker_tone() {
+        let mut huc3 = huc3(|| 0);
+        assert_eq!(huc3.speaker_tone(), None);
+        // tone 5 into 0x27, then the play command
+        rtc_command(&mut huc3, 0x47);
+        rtc_command(&mut huc3, 0x52);
+        rtc_command(&mut huc3, 0x35);
+        rtc_command(&mut huc3, 0x6E);
+        let mbc: Box<dyn MBC> = Box::new(huc3);
+        assert_eq!(mbc.speaker_tone(), Some(5));
+    }
+}
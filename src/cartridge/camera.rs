@@ -0,0 +1,478 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{load_ram, ReadableMemory, WritableMemory, MBC};
+
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+
+const RAM_SIZE: usize = 0x20000;
+// processed pictures land in RAM bank 0 at 0x0100, as 16x14 2bpp tiles
+const PICTURE_OFFSET: usize = 0x0100;
+// exposure register value that passes the sensor image through unchanged
+const UNITY_EXPOSURE: u32 = 0x1000;
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+pub type Frame = Box<[u8; SENSOR_WIDTH * SENSOR_HEIGHT]>;
+
+/// Where the camera gets its 128x112 grayscale pictures from, 0 is black and 255 white.
+pub enum CameraSource {
+    /// A procedural pattern of gradients and bars, handy for headless tests.
+    TestPattern,
+    /// The same picture every time, e.g. loaded from a file.
+    Still(Frame),
+    /// Asked for a new picture on every capture.
+    Callback(Box<dyn FnMut() -> Frame>),
+}
+
+impl CameraSource {
+    /// Loads a binary PGM (P5) image of exactly 128x112 pixels.
+    pub fn from_pgm(path: &Path) -> io::Result<CameraSource> {
+        Ok(CameraSource::Still(read_pgm(path)?))
+    }
+
+    /// Reads the PGM again on every capture, so another program can keep replacing it
+    /// with webcam frames. A read that fails keeps the last good picture.
+    pub fn from_pgm_live(path: &Path) -> io::Result<CameraSource> {
+        let mut frame = read_pgm(path)?;
+        let path = path.to_path_buf();
+        Ok(CameraSource::Callback(Box::new(move || {
+            if let Ok(new_frame) = read_pgm(&path) {
+                frame = new_frame;
+            }
+            frame.clone()
+        })))
+    }
+
+    fn capture(&mut self) -> Frame {
+        match self {
+            CameraSource::TestPattern => {
+                let mut frame: Frame = Box::new([0u8; SENSOR_WIDTH * SENSOR_HEIGHT]);
+                for y in 0..SENSOR_HEIGHT {
+                    for x in 0..SENSOR_WIDTH {
+                        // horizontal gradient on top, vertical bars below
+                        frame[y * SENSOR_WIDTH + x] = if y < SENSOR_HEIGHT / 2 {
+                            (x * 2) as u8
+                        } else {
+                            (x / 16 * 36) as u8
+                        };
+                    }
+                }
+                frame
+            }
+            CameraSource::Still(frame) => frame.clone(),
+            CameraSource::Callback(callback) => callback(),
+        }
+    }
+}
+
+// binary PGM (P5), scaled from its max value to 0-255
+fn read_pgm(path: &Path) -> io::Result<Frame> {
+    let data = fs::read(path)?;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    // header is "P5 <width> <height> <maxval>" followed by a single whitespace byte
+    let mut fields = Vec::new();
+    let mut position = 0;
+    while fields.len() < 4 {
+        while position < data.len() && data[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position < data.len() && data[position] == b'#' {
+            while position < data.len() && data[position] != b'\n' {
+                position += 1;
+            }
+            continue;
+        }
+        let start = position;
+        while position < data.len() && !data[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            return Err(invalid("truncated PGM header"));
+        }
+        fields.push(String::from_utf8_lossy(&data[start..position]).to_string());
+    }
+    position += 1;
+
+    if fields[0] != "P5" {
+        return Err(invalid("only binary PGM (P5) images are supported"));
+    }
+    let number = |field: &String| {
+        field
+            .parse::<usize>()
+            .map_err(|_| invalid("bad PGM header"))
+    };
+    let (width, height, max_value) = (
+        number(&fields[1])?,
+        number(&fields[2])?,
+        number(&fields[3])?,
+    );
+    if width != SENSOR_WIDTH || height != SENSOR_HEIGHT {
+        return Err(invalid("camera images must be 128x112"));
+    }
+    if max_value == 0 || max_value > 255 {
+        return Err(invalid("only 8 bit PGM images are supported"));
+    }
+
+    let pixels = data
+        .get(position..position + SENSOR_WIDTH * SENSOR_HEIGHT)
+        .ok_or_else(|| invalid("truncated PGM image"))?;
+    let mut frame: Frame = Box::new([0u8; SENSOR_WIDTH * SENSOR_HEIGHT]);
+    for (pixel, &value) in frame.iter_mut().zip(pixels) {
+        *pixel = (value as usize * 255 / max_value) as u8;
+    }
+    Ok(frame)
+}
+
+/// The Game Boy Camera cartridge, with the M64282FP sensor behind RAM bank 0x10.
+pub struct PocketCamera {
+    rom_bank_select: u8,
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    ram_enabled: bool,
+    ram_bank_select: u8,
+    ram: Box<[u8]>,
+    ram_dirty: bool,
+    registers: [u8; 0x36],
+    // CPU ticks until the capture in progress finishes
+    capture_ticks: Option<u64>,
+    source: CameraSource,
+}
+
+impl PocketCamera {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> PocketCamera {
+        PocketCamera {
+            rom_bank_select: 1,
+            rom_banks,
+            ram_enabled: false,
+            ram_bank_select: 0,
+            ram: vec![0u8; RAM_SIZE].into_boxed_slice(),
+            ram_dirty: false,
+            registers: [0u8; 0x36],
+            capture_ticks: None,
+            source: CameraSource::TestPattern,
+        }
+    }
+
+    fn registers_selected(&self) -> bool {
+        self.ram_bank_select & 0x10 == 0x10
+    }
+
+    fn exposure(&self) -> u32 {
+        (self.registers[0x02] as u32) << 8 | self.registers[0x03] as u32
+    }
+
+    fn start_capture(&mut self) {
+        // in CPU ticks, the sensor counts in units of 16 M-cycles of exposure
+        let n_bit = self.registers[0x01] & 0x80 == 0x80;
+        let cycles = 32446 + if n_bit { 0 } else { 512 } + 16 * self.exposure() as u64;
+        self.capture_ticks = Some(cycles * 4);
+    }
+
+    fn sensor_image(&mut self) -> Vec<f32> {
+        let frame = self.source.capture();
+        let exposure = self.exposure();
+        let mut image: Vec<f32> = frame
+            .iter()
+            .map(|&pixel| (pixel as u32 * exposure / UNITY_EXPOSURE).min(255) as f32)
+            .collect();
+
+        if self.registers[0x04] & 0x80 == 0x80 {
+            // edge enhancement sharpens against the four neighbours
+            let alpha = EDGE_RATIOS[((self.registers[0x04] >> 4) & 0x07) as usize];
+            let source = image.clone();
+            let at = |x: isize, y: isize| {
+                let x = x.clamp(0, SENSOR_WIDTH as isize - 1) as usize;
+                let y = y.clamp(0, SENSOR_HEIGHT as isize - 1) as usize;
+                source[y * SENSOR_WIDTH + x]
+            };
+            for y in 0..SENSOR_HEIGHT as isize {
+                for x in 0..SENSOR_WIDTH as isize {
+                    let neighbours = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1);
+                    let value = at(x, y) + alpha * (4.0 * at(x, y) - neighbours) / 4.0;
+                    image[y as usize * SENSOR_WIDTH + x as usize] = value.clamp(0.0, 255.0);
+                }
+            }
+        }
+
+        if self.registers[0x04] & 0x08 == 0x08 {
+            image.iter_mut().for_each(|pixel| *pixel = 255.0 - *pixel);
+        }
+        image
+    }
+
+    // dithers the picture through the 4x4 threshold matrix into 2bpp tiles in RAM
+    fn finish_capture(&mut self) {
+        let image = self.sensor_image();
+        let matrix = &self.registers[0x06..0x36];
+
+        for y in 0..SENSOR_HEIGHT {
+            for x in 0..SENSOR_WIDTH {
+                let thresholds = &matrix[((y & 3) * 4 + (x & 3)) * 3..][..3];
+                let pixel = image[y * SENSOR_WIDTH + x];
+                // darker than a threshold means a darker shade, shade 3 is black
+                let shade = if pixel < thresholds[0] as f32 {
+                    3
+                } else if pixel < thresholds[1] as f32 {
+                    2
+                } else if pixel < thresholds[2] as f32 {
+                    1
+                } else {
+                    0
+                };
+
+                let tile = (y / 8) * (SENSOR_WIDTH / 8) + x / 8;
+                let offset = PICTURE_OFFSET + tile * 16 + (y % 8) * 2;
+                let bit = 0x80 >> (x % 8);
+                if shade & 0x01 == 0x01 {
+                    self.ram[offset] |= bit;
+                } else {
+                    self.ram[offset] &= !bit;
+                }
+                if shade & 0x02 == 0x02 {
+                    self.ram[offset + 1] |= bit;
+                } else {
+                    self.ram[offset + 1] &= !bit;
+                }
+            }
+        }
+
+        self.registers[0x00] &= !0x01;
+        self.ram_dirty = true;
+    }
+}
+
+impl ReadableMemory for PocketCamera {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.rom_banks[0][address],
+            0x4000..=0x7FFF => {
+                let bank_select = self.rom_bank_select as usize % self.rom_banks.len();
+                self.rom_banks[bank_select][address & 0x3FFF]
+            }
+            0xA000..=0xBFFF if self.registers_selected() => {
+                // only the busy flag can be read back
+                match address & 0x7F {
+                    0x00 => self.registers[0x00] & 0x07,
+                    _ => 0x00,
+                }
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+                // the sensor owns RAM while it's writing a picture
+                if self.capture_ticks.is_some() {
+                    return 0x00;
+                }
+                let bank = (self.ram_bank_select & 0x0F) as usize;
+                self.ram[bank * 0x2000 + (address & 0x1FFF)]
+            }
+            _ => {
+                panic!("invalid address to read in PocketCamera, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for PocketCamera {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+            }
+            0x2000..=0x3FFF => {
+                self.rom_bank_select = value & 0x3F;
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_select = value & 0x1F;
+            }
+            0x6000..=0x7FFF => {}
+            0xA000..=0xBFFF if self.registers_selected() => {
+                let register = address & 0x7F;
+                if register == 0x00 {
+                    self.registers[0x00] = value & 0x07;
+                    if value & 0x01 == 0x01 && self.capture_ticks.is_none() {
+                        self.start_capture();
+                    } else if value & 0x01 == 0x00 {
+                        // clearing the start bit cancels a capture in progress
+                        self.capture_ticks = None;
+                    }
+                } else if register < self.registers.len() {
+                    self.registers[register] = value;
+                }
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled || self.capture_ticks.is_some() {
+                    return;
+                }
+                let bank = (self.ram_bank_select & 0x0F) as usize;
+                self.ram[bank * 0x2000 + (address & 0x1FFF)] = value;
+                self.ram_dirty = true;
+            }
+            _ => {
+                panic!("invalid address to write to PocketCamera");
+            }
+        }
+    }
+}
+
+impl MBC for PocketCamera {
+    fn tick(&mut self, ticks: u64) {
+        if let Some(remaining) = self.capture_ticks {
+            if remaining <= ticks {
+                self.capture_ticks = None;
+                self.finish_capture();
+            } else {
+                self.capture_ticks = Some(remaining - ticks);
+            }
+        }
+    }
+
+    fn set_camera_source(&mut self, source: CameraSource) {
+        self.source = source;
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom_banks;
+    use std::env;
+
+    fn camera() -> PocketCamera {
+        let mut camera = PocketCamera::new(rom_banks(&[0u8; 0x8000]));
+        camera.write(0x0000, 0x0A);
+        camera.write(0x4000, 0x10);
+        // unity exposure and an even matrix of 0x40/0x80/0xC0 thresholds
+        camera.write(0xA002, 0x10);
+        camera.write(0xA003, 0x00);
+        for i in 0..16 {
+            camera.write(0xA006 + i * 3, 0x40);
+            camera.write(0xA007 + i * 3, 0x80);
+            camera.write(0xA008 + i * 3, 0xC0);
+        }
+        camera
+    }
+
+    fn capture(camera: &mut PocketCamera, source: CameraSource) {
+        camera.set_camera_source(source);
+        camera.write(0x4000, 0x10);
+        camera.write(0xA000, 0x01);
+        camera.tick(u64::MAX);
+        camera.write(0x4000, 0x00);
+    }
+
+    fn still(value: u8) -> CameraSource {
+        CameraSource::Still(Box::new([value; SENSOR_WIDTH * SENSOR_HEIGHT]))
+    }
+
+    #[test]
+    fn test_ram_reads_need_enable() {
+        let mut camera = camera();
+        camera.write(0x4000, 0x00);
+        camera.write(0xA000, 0x69);
+        assert_eq!(camera.read(0xA000), 0x69);
+
+        camera.write(0x0000, 0x00);
+        assert_eq!(camera.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn test_capture_busy_until_exposure_done() {
+        let mut camera = camera();
+        camera.write(0xA000, 0x01);
+        assert_eq!(camera.read(0xA000), 0x01);
+        camera.tick(4 * 32446);
+        assert_eq!(camera.read(0xA000), 0x01);
+        camera.tick(4 * (512 + 16 * 0x1000));
+        assert_eq!(camera.read(0xA000), 0x00);
+        // everything but the busy flag reads back as 0
+        assert_eq!(camera.read(0xA003), 0x00);
+    }
+
+    #[test]
+    fn test_dither_to_shades() {
+        let mut camera = camera();
+        capture(&mut camera, still(0x00));
+        assert_eq!(camera.read(0xA100), 0xFF);
+        assert_eq!(camera.read(0xA101), 0xFF);
+
+        capture(&mut camera, still(0x60));
+        assert_eq!(camera.read(0xA100), 0x00);
+        assert_eq!(camera.read(0xA101), 0xFF);
+
+        capture(&mut camera, still(0xFF));
+        assert_eq!(camera.read(0xA100), 0x00);
+        assert_eq!(camera.read(0xA101), 0x00);
+        assert!(camera.take_save_dirty());
+    }
+
+    #[test]
+    fn test_invert() {
+        let mut camera = camera();
+        camera.write(0xA004, 0x08);
+        capture(&mut camera, still(0x00));
+        assert_eq!(camera.read(0xA100), 0x00);
+        assert_eq!(camera.read(0xA101), 0x00);
+    }
+
+    #[test]
+    fn test_callback_source() {
+        let mut camera = camera();
+        let source = CameraSource::Callback(Box::new(|| {
+            let mut frame: Frame = Box::new([0xFF; SENSOR_WIDTH * SENSOR_HEIGHT]);
+            frame[0] = 0x00;
+            frame
+        }));
+        capture(&mut camera, source);
+        assert_eq!(camera.read(0xA100), 0x80);
+        assert_eq!(camera.read(0xA101), 0x80);
+    }
+
+    #[test]
+    fn test_test_pattern_has_every_shade() {
+        let mut camera = camera();
+        capture(&mut camera, CameraSource::TestPattern);
+        // the first tile row is the left end of the gradient, the last tile of it the right end
+        assert_eq!(camera.read(0xA101), 0xFF);
+        assert_eq!(camera.read(0xA100 + 15 * 16), 0x00);
+        assert_eq!(camera.read(0xA101 + 15 * 16), 0x00);
+    }
+
+    #[test]
+    fn test_pgm_source() {
+        let path = env::temp_dir().join(format!("gb-camera-{}.pgm", std::process::id()));
+        let mut pgm = b"P5\n# test\n128 112\n255\n".to_vec();
+        pgm.resize(pgm.len() + SENSOR_WIDTH * SENSOR_HEIGHT, 0x00);
+        fs::write(&path, pgm).unwrap();
+
+        let mut camera = camera();
+        capture(&mut camera, CameraSource::from_pgm(&path).unwrap());
+        assert_eq!(camera.read(0xA100), 0xFF);
+
+        let live = CameraSource::from_pgm_live(&path).unwrap();
+        let mut pgm = b"P5 128 112 255\n".to_vec();
+        pgm.resize(pgm.len() + SENSOR_WIDTH * SENSOR_HEIGHT, 0xFF);
+        fs::write(&path, pgm).unwrap();
+        capture(&mut camera, live);
+        assert_eq!(camera.read(0xA100), 0x00);
+
+        fs::write(&path, b"P5 64 64 255\n").unwrap();
+        assert!(CameraSource::from_pgm(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use super::{CartridgeInfo, Mapper};
+
+const FOOTER_SIZE: usize = 0x40;
+// the largest ROM and RAM chips any known mapper can address
+const MAX_ROM_SIZE: usize = 0x800000;
+const MAX_RAM_SIZE: usize = 0x20000;
+
+/// The GBX footer some dumps and homebrew carry after the ROM data, describing
+/// the cartridge hardware independently of the header byte.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GbxFooter {
+    pub mapper_id: [u8; 4],
+    pub has_battery: bool,
+    pub has_rumble: bool,
+    pub has_rtc: bool,
+    pub rom_size: usize,
+    pub ram_size: usize,
+}
+
+impl GbxFooter {
+    /// Removes a GBX footer from the end of `rom` if there is one, so the rest of
+    /// the loader only ever sees ROM data.
+    pub fn split(rom: &mut Vec<u8>) -> Option<GbxFooter> {
+        let footer = GbxFooter::parse(rom)?;
+        rom.truncate(rom.len() - FOOTER_SIZE);
+        Some(footer)
+    }
+
+    fn parse(rom: &[u8]) -> Option<GbxFooter> {
+        if rom.len() < FOOTER_SIZE || !rom.ends_with(b"GBX!") {
+            return None;
+        }
+        let footer = &rom[rom.len() - FOOTER_SIZE..];
+        let u32_at = |offset: usize| {
+            u32::from_be_bytes([
+                footer[offset],
+                footer[offset + 1],
+                footer[offset + 2],
+                footer[offset + 3],
+            ]) as usize
+        };
+
+        // only the 1.x layout is known, newer majors may move things around
+        if u32_at(0x30) != FOOTER_SIZE || u32_at(0x34) != 1 {
+            return None;
+        }
+        // sizes no cart could have mean this isn't really a footer
+        if u32_at(0x08) > MAX_ROM_SIZE || u32_at(0x0C) > MAX_RAM_SIZE {
+            return None;
+        }
+
+        Some(GbxFooter {
+            mapper_id: [footer[0], footer[1], footer[2], footer[3]],
+            has_battery: footer[4] != 0,
+            has_rumble: footer[5] != 0,
+            has_rtc: footer[6] != 0,
+            rom_size: u32_at(0x08),
+            ram_size: u32_at(0x0C),
+        })
+    }
+
+    pub fn mapper(&self) -> Option<Mapper> {
+        let mapper = match &self.mapper_id {
+            b"ROM\0" => Mapper::RomOnly,
+            b"MBC1" => Mapper::MBC1,
+            b"MB1M" => Mapper::MBC1M,
+            b"MBC2" => Mapper::MBC2,
+            b"MBC3" => Mapper::MBC3,
+            b"MBC5" => Mapper::MBC5,
+            b"MBC6" => Mapper::MBC6,
+            b"MBC7" => Mapper::MBC7,
+            b"MMM1" => Mapper::MMM01,
+            b"CAMR" => Mapper::PocketCamera,
+            b"TAM5" => Mapper::TAMA5,
+            b"HUC1" => Mapper::HuC1,
+            b"HUC3" => Mapper::HuC3,
+            b"WISD" => Mapper::WisdomTree,
+            b"SAM1" => Mapper::SachenMMC1,
+            b"SAM2" => Mapper::SachenMMC2,
+            _ => return None,
+        };
+        Some(mapper)
+    }
+}
+
+impl CartridgeInfo {
+    /// Trusts the footer over the header, keeping the header's mapper if the
+    /// footer names one we don't know.
+    pub fn apply_gbx(&mut self, gbx: &GbxFooter) {
+        if let Some(mapper) = gbx.mapper() {
+            self.mapper = mapper;
+        }
+        self.has_battery = gbx.has_battery;
+        self.has_rumble = gbx.has_rumble;
+        self.has_rtc = gbx.has_rtc;
+        self.rom_size = Some(gbx.rom_size);
+        self.ram_size = gbx.ram_size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn footer(mapper_id: &[u8; 4], flags: [u8; 3], rom_size: u32, ram_size: u32) -> Vec<u8> {
+        let mut footer = mapper_id.to_vec();
+        footer.extend_from_slice(&flags);
+        footer.push(0);
+        footer.extend_from_slice(&rom_size.to_be_bytes());
+        footer.extend_from_slice(&ram_size.to_be_bytes());
+        footer.extend_from_slice(&[0u8; 0x20]);
+        footer.extend_from_slice(&0x40u32.to_be_bytes());
+        footer.extend_from_slice(&1u32.to_be_bytes());
+        footer.extend_from_slice(&0u32.to_be_bytes());
+        footer.extend_from_slice(b"GBX!");
+        footer
+    }
+
+    #[test]
+    fn test_split_footer() {
+        let mut rom = vec![0u8; 0x8000];
+        rom.extend(footer(b"MBC5", [1, 1, 0], 0x8000, 0x2000));
+
+        let gbx = GbxFooter::split(&mut rom).unwrap();
+        assert_eq!(rom.len(), 0x8000);
+        assert_eq!(gbx.mapper(), Some(Mapper::MBC5));
+        assert!(gbx.has_battery);
+        assert!(gbx.has_rumble);
+        assert!(!gbx.has_rtc);
+        assert_eq!(gbx.ram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_multicart_mapper() {
+        let mut rom = vec![0u8; 0x8000];
+        rom.extend(footer(b"MB1M", [0, 0, 0], 0x8000, 0));
+        let gbx = GbxFooter::split(&mut rom).unwrap();
+        assert_eq!(gbx.mapper(), Some(Mapper::MBC1M));
+    }
+
+    #[test]
+    fn test_no_footer() {
+        let mut rom = vec![0u8; 0x8000];
+        assert_eq!(GbxFooter::split(&mut rom), None);
+        assert_eq!(rom.len(), 0x8000);
+    }
+
+    #[test]
+    fn test_unknown_major_version_is_ignored() {
+        let mut rom = vec![0u8; 0x8000];
+        let mut gbx = footer(b"MBC5", [0, 0, 0], 0x8000, 0);
+        gbx[0x37] = 2;
+        rom.extend(gbx);
+        assert_eq!(GbxFooter::split(&mut rom), None);
+    }
+
+    #[test]
+    fn test_impossible_sizes_are_ignored() {
+        let mut rom = vec![0u8; 0x8000];
+        rom.extend(footer(b"MBC5", [0, 0, 0], 0xFFFF_FFFF, 0));
+        assert_eq!(GbxFooter::split(&mut rom), None);
+
+        let mut rom = vec![0u8; 0x8000];
+        rom.extend(footer(b"MBC5", [0, 0, 0], 0x8000, 0x8000_0000));
+        assert_eq!(GbxFooter::split(&mut rom), None);
+    }
+
+    #[test]
+    fn test_overrides_header() {
+        // header claims a plain ROM
+        let mut rom = vec![0u8; 0x8000];
+        rom.extend(footer(b"HUC3", [1, 0, 1], 0x8000, 0x8000));
+        let gbx = GbxFooter::split(&mut rom).unwrap();
+
+        let mut info = CartridgeInfo::from_header(&rom).unwrap();
+        assert_eq!(info.mapper, Mapper::RomOnly);
+        info.apply_gbx(&gbx);
+        assert_eq!(info.mapper, Mapper::HuC3);
+        assert_eq!(info.ram_size, 0x8000);
+        assert!(info.has_battery);
+        assert!(info.has_rtc);
+    }
+
+    #[test]
+    fn test_unknown_mapper_keeps_header() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x1B;
+        rom.extend(footer(b"ZZZZ", [0, 0, 0], 0x8000, 0));
+        let gbx = GbxFooter::split(&mut rom).unwrap();
+
+        let mut info = CartridgeInfo::from_header(&rom).unwrap();
+        info.apply_gbx(&gbx);
+        assert_eq!(info.mapper, Mapper::MBC5);
+        assert!(!info.has_battery);
+    }
+}
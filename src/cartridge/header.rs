@@ -0,0 +1,251 @@
+use super::ram_size;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapper {
+    RomOnly,
+    MBC1,
+    // MBC1 multicart, only told apart by a GBX footer or --mapper
+    MBC1M,
+    MBC2,
+    MMM01,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    PocketCamera,
+    TAMA5,
+    HuC3,
+    HuC1,
+    WisdomTree,
+    SachenMMC1,
+    SachenMMC2,
+    // the address the bootleg board takes its ROM bank number at
+    BootlegMBC1(u16),
+    BootlegMBC5(u16),
+    Unknown(u8),
+}
+
+impl Mapper {
+    /// Parses the names accepted by `--mapper`, for carts whose header can't be trusted.
+    pub fn from_name(name: &str) -> Option<Mapper> {
+        let bank_register = |name: &str, prefix: &str| {
+            let address = name.strip_prefix(prefix)?;
+            let address = address.strip_prefix("0x").unwrap_or(address);
+            u16::from_str_radix(address, 16).ok()
+        };
+
+        let mapper = match name.to_ascii_lowercase().as_str() {
+            "rom" => Mapper::RomOnly,
+            "mbc1" => Mapper::MBC1,
+            "mbc1m" => Mapper::MBC1M,
+            "mbc2" => Mapper::MBC2,
+            "mmm01" => Mapper::MMM01,
+            "mbc3" => Mapper::MBC3,
+            "mbc5" => Mapper::MBC5,
+            "mbc6" => Mapper::MBC6,
+            "mbc7" => Mapper::MBC7,
+            "camera" => Mapper::PocketCamera,
+            "tama5" => Mapper::TAMA5,
+            "huc1" => Mapper::HuC1,
+            "huc3" => Mapper::HuC3,
+            "wisdom-tree" => Mapper::WisdomTree,
+            "sachen-mmc1" => Mapper::SachenMMC1,
+            "sachen-mmc2" => Mapper::SachenMMC2,
+            name if name.starts_with("bootleg-mbc1:") => {
+                Mapper::BootlegMBC1(bank_register(name, "bootleg-mbc1:")?)
+            }
+            name if name.starts_with("bootleg-mbc5:") => {
+                Mapper::BootlegMBC5(bank_register(name, "bootleg-mbc5:")?)
+            }
+            _ => return None,
+        };
+        Some(mapper)
+    }
+}
+
+impl fmt::Display for Mapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mapper::RomOnly => write!(f, "ROM only"),
+            Mapper::MBC1 => write!(f, "MBC1"),
+            Mapper::MBC1M => write!(f, "MBC1 multicart"),
+            Mapper::MBC2 => write!(f, "MBC2"),
+            Mapper::MMM01 => write!(f, "MMM01"),
+            Mapper::MBC3 => write!(f, "MBC3"),
+            Mapper::MBC5 => write!(f, "MBC5"),
+            Mapper::MBC6 => write!(f, "MBC6"),
+            Mapper::MBC7 => write!(f, "MBC7"),
+            Mapper::PocketCamera => write!(f, "Pocket Camera"),
+            Mapper::TAMA5 => write!(f, "TAMA5"),
+            Mapper::HuC3 => write!(f, "HuC3"),
+            Mapper::HuC1 => write!(f, "HuC1"),
+            Mapper::WisdomTree => write!(f, "Wisdom Tree"),
+            Mapper::SachenMMC1 => write!(f, "Sachen MMC1"),
+            Mapper::SachenMMC2 => write!(f, "Sachen MMC2"),
+            Mapper::BootlegMBC1(bank_register) => {
+                write!(f, "bootleg MBC1 (bank register {:04X})", bank_register)
+            }
+            Mapper::BootlegMBC5(bank_register) => {
+                write!(f, "bootleg MBC5 (bank register {:04X})", bank_register)
+            }
+            Mapper::Unknown(cartridge_type) => write!(f, "unknown ({:02X})", cartridge_type),
+        }
+    }
+}
+
+pub(crate) const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderError {
+    TooShort(usize),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::TooShort(length) => write!(
+                f,
+                "ROM is {} bytes, too small to have a cartridge header",
+                length
+            ),
+        }
+    }
+}
+
+/// What the cartridge header at 0x0100..=0x014F says about the hardware on the cart.
+#[derive(Debug, Clone)]
+pub struct CartridgeInfo {
+    pub cartridge_type: u8,
+    pub mapper: Mapper,
+    // None when the size byte isn't one of the known codes
+    pub rom_size: Option<usize>,
+    pub ram_size: usize,
+    pub has_battery: bool,
+    pub has_rtc: bool,
+    pub has_rumble: bool,
+}
+
+impl CartridgeInfo {
+    pub fn from_header(rom: &[u8]) -> Result<CartridgeInfo, HeaderError> {
+        if rom.len() < 0x0150 {
+            return Err(HeaderError::TooShort(rom.len()));
+        }
+
+        let cartridge_type = rom[0x0147];
+        let (mapper, has_battery, has_rtc, has_rumble) = match cartridge_type {
+            0x00 | 0x08 => (Mapper::RomOnly, false, false, false),
+            0x09 => (Mapper::RomOnly, true, false, false),
+            0x01 | 0x02 => (Mapper::MBC1, false, false, false),
+            0x03 => (Mapper::MBC1, true, false, false),
+            0x05 => (Mapper::MBC2, false, false, false),
+            0x06 => (Mapper::MBC2, true, false, false),
+            0x0B | 0x0C => (Mapper::MMM01, false, false, false),
+            0x0D => (Mapper::MMM01, true, false, false),
+            0x0F | 0x10 => (Mapper::MBC3, true, true, false),
+            0x11 | 0x12 => (Mapper::MBC3, false, false, false),
+            0x13 => (Mapper::MBC3, true, false, false),
+            0x19 | 0x1A => (Mapper::MBC5, false, false, false),
+            0x1B => (Mapper::MBC5, true, false, false),
+            0x1C | 0x1D => (Mapper::MBC5, false, false, true),
+            0x1E => (Mapper::MBC5, true, false, true),
+            // the flash chip keeps its contents like battery RAM
+            0x20 => (Mapper::MBC6, true, false, false),
+            0x22 => (Mapper::MBC7, true, false, false),
+            0xFC => (Mapper::PocketCamera, true, false, false),
+            0xFD => (Mapper::TAMA5, true, true, false),
+            0xFE => (Mapper::HuC3, true, true, false),
+            0xFF => (Mapper::HuC1, true, false, false),
+            _ => (Mapper::Unknown(cartridge_type), false, false, false),
+        };
+
+        Ok(CartridgeInfo {
+            cartridge_type,
+            mapper,
+            rom_size: rom_size(rom[0x0148]),
+            ram_size: ram_size(rom[0x0149]),
+            has_battery,
+            has_rtc,
+            has_rumble,
+        })
+    }
+}
+
+/// Size in bytes of the ROM described by the header byte at 0x0148.
+pub fn rom_size(code: u8) -> Option<usize> {
+    match code {
+        0x00..=0x08 => Some(0x8000 << code),
+        // odd sizes made of a 1MB chip plus a smaller one, listed in old docs
+        0x52 => Some(72 * 0x4000),
+        0x53 => Some(80 * 0x4000),
+        0x54 => Some(96 * 0x4000),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(cartridge_type: u8, ram_size_code: u8) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = cartridge_type;
+        rom[0x0149] = ram_size_code;
+        rom
+    }
+
+    #[test]
+    fn test_battery_backed_mbc3() {
+        let info = CartridgeInfo::from_header(&header(0x10, 0x03)).unwrap();
+        assert_eq!(info.mapper, Mapper::MBC3);
+        assert_eq!(info.ram_size, 0x8000);
+        assert!(info.has_battery);
+        assert!(info.has_rtc);
+    }
+
+    #[test]
+    fn test_mbc5_rumble_without_battery() {
+        let info = CartridgeInfo::from_header(&header(0x1C, 0x00)).unwrap();
+        assert_eq!(info.mapper, Mapper::MBC5);
+        assert!(info.has_rumble);
+        assert!(!info.has_battery);
+    }
+
+    #[test]
+    fn test_rom_size_codes() {
+        assert_eq!(rom_size(0x00), Some(0x8000));
+        assert_eq!(rom_size(0x08), Some(0x800000));
+        assert_eq!(rom_size(0x52), Some(0x120000));
+        assert_eq!(rom_size(0x09), None);
+        assert_eq!(rom_size(0xFF), None);
+
+        let mut rom = header(0x00, 0x00);
+        rom[0x0148] = 0x52;
+        let info = CartridgeInfo::from_header(&rom).unwrap();
+        assert_eq!(info.rom_size, Some(0x120000));
+    }
+
+    #[test]
+    fn test_short_rom() {
+        assert_eq!(
+            CartridgeInfo::from_header(&[0u8; 0x100]).err(),
+            Some(HeaderError::TooShort(0x100))
+        );
+    }
+
+    #[test]
+    fn test_mapper_from_name() {
+        assert_eq!(Mapper::from_name("MBC5"), Some(Mapper::MBC5));
+        assert_eq!(Mapper::from_name("wisdom-tree"), Some(Mapper::WisdomTree));
+        assert_eq!(
+            Mapper::from_name("bootleg-mbc1:0x7000"),
+            Some(Mapper::BootlegMBC1(0x7000))
+        );
+        assert_eq!(Mapper::from_name("bootleg-mbc5:zz"), None);
+        assert_eq!(Mapper::from_name("mbc4"), None);
+    }
+}
@@ -0,0 +1,301 @@
+use super::{load_ram, ReadableMemory, WritableMemory, MBC};
+
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+const RAM_SIZE: usize = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FlashCommand {
+    // waiting for 0xAA at 0x5555
+    Ready,
+    // got 0xAA, waiting for 0x55 at 0x2AAA
+    Unlock1,
+    // unlocked, the next write at 0x5555 is the command
+    Unlock2,
+    // 0xA0, the next write is programmed
+    Program,
+    // 0x80, an erase needs a second unlock sequence
+    EraseReady,
+    EraseUnlock1,
+    EraseUnlock2,
+    // 0x90, reads return the chip IDs until reset
+    Identify,
+}
+
+// one of the two 8KB windows at 0x4000 and 0x6000
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    bank: u8,
+    flash: bool,
+}
+
+/// MBC6 splits the switchable ROM area into two 8KB windows, each of which can
+/// map either ROM or the cart's 1MB flash chip, and RAM into two 4KB windows.
+pub struct MBC6 {
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    windows: [Window; 2],
+    ram_enabled: bool,
+    ram_banks: [u8; 2],
+    ram: Box<[u8]>,
+    flash: Box<[u8]>,
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash_command: FlashCommand,
+    dirty: bool,
+}
+
+impl MBC6 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> MBC6 {
+        MBC6 {
+            rom_banks,
+            windows: [Window {
+                bank: 0,
+                flash: false,
+            }; 2],
+            ram_enabled: false,
+            ram_banks: [0; 2],
+            ram: vec![0u8; RAM_SIZE].into_boxed_slice(),
+            flash: vec![0xFFu8; FLASH_SIZE].into_boxed_slice(),
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash_command: FlashCommand::Ready,
+            dirty: false,
+        }
+    }
+
+    fn window_offset(window: Window, address: usize) -> usize {
+        window.bank as usize * 0x2000 + (address & 0x1FFF)
+    }
+
+    fn read_rom(&self, offset: usize) -> u8 {
+        let bank = (offset / 0x4000) % self.rom_banks.len();
+        self.rom_banks[bank][offset & 0x3FFF]
+    }
+
+    fn ram_offset(&self, address: usize) -> usize {
+        let window = (address >> 12) & 0x01;
+        let bank = self.ram_banks[window] as usize;
+        (bank * 0x1000 + (address & 0x0FFF)) % self.ram.len()
+    }
+
+    fn write_flash(&mut self, window: Window, address: usize, value: u8) {
+        let offset = MBC6::window_offset(window, address) % FLASH_SIZE;
+        // the unlock addresses are decoded from the low 15 bits of the flash address
+        let command_address = offset & 0x7FFF;
+
+        self.flash_command = match (self.flash_command, command_address, value) {
+            (_, _, 0xF0) => FlashCommand::Ready,
+            (FlashCommand::Ready, 0x5555, 0xAA) => FlashCommand::Unlock1,
+            (FlashCommand::Unlock1, 0x2AAA, 0x55) => FlashCommand::Unlock2,
+            (FlashCommand::Unlock2, 0x5555, 0xA0) => FlashCommand::Program,
+            (FlashCommand::Unlock2, 0x5555, 0x80) => FlashCommand::EraseReady,
+            (FlashCommand::Unlock2, 0x5555, 0x90) => FlashCommand::Identify,
+            (FlashCommand::EraseReady, 0x5555, 0xAA) => FlashCommand::EraseUnlock1,
+            (FlashCommand::EraseUnlock1, 0x2AAA, 0x55) => FlashCommand::EraseUnlock2,
+            (FlashCommand::EraseUnlock2, _, 0x30) => {
+                if self.flash_write_enabled {
+                    let sector = offset - offset % FLASH_SECTOR_SIZE;
+                    self.flash[sector..sector + FLASH_SECTOR_SIZE].fill(0xFF);
+                    self.dirty = true;
+                }
+                FlashCommand::Ready
+            }
+            (FlashCommand::EraseUnlock2, 0x5555, 0x10) => {
+                if self.flash_write_enabled {
+                    self.flash.fill(0xFF);
+                    self.dirty = true;
+                }
+                FlashCommand::Ready
+            }
+            (FlashCommand::Program, _, _) => {
+                if self.flash_write_enabled {
+                    // programming can only clear bits, erasing is what sets them
+                    self.flash[offset] &= value;
+                    self.dirty = true;
+                }
+                FlashCommand::Ready
+            }
+            (FlashCommand::Identify, _, _) => FlashCommand::Identify,
+            _ => FlashCommand::Ready,
+        };
+    }
+}
+
+impl ReadableMemory for MBC6 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.rom_banks[0][address],
+            0x4000..=0x7FFF => {
+                let window = self.windows[(address >> 13) & 0x01];
+                if !window.flash {
+                    return self.read_rom(MBC6::window_offset(window, address));
+                }
+                if !self.flash_enabled {
+                    return 0xFF;
+                }
+                if self.flash_command == FlashCommand::Identify {
+                    // Macronix manufacturer and device IDs
+                    return match address & 0x01 {
+                        0 => 0xC2,
+                        _ => 0x81,
+                    };
+                }
+                self.flash[MBC6::window_offset(window, address) % FLASH_SIZE]
+            }
+            0xA000..=0xBFFF if self.ram_enabled => self.ram[self.ram_offset(address)],
+            0xA000..=0xBFFF => 0xFF,
+            _ => {
+                panic!("invalid address to read in MBC6, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for MBC6 {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x03FF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_banks[0] = value & 0x07,
+            0x0800..=0x0BFF => self.ram_banks[1] = value & 0x07,
+            0x0C00..=0x0FFF => self.flash_enabled = value & 0x01 == 0x01,
+            0x1000 => self.flash_write_enabled = value & 0x01 == 0x01,
+            0x1001..=0x1FFF => {}
+            0x2000..=0x27FF => self.windows[0].bank = value & 0x7F,
+            0x2800..=0x2FFF => self.windows[0].flash = value == 0x08,
+            0x3000..=0x37FF => self.windows[1].bank = value & 0x7F,
+            0x3800..=0x3FFF => self.windows[1].flash = value == 0x08,
+            0x4000..=0x7FFF => {
+                let window = self.windows[(address >> 13) & 0x01];
+                if window.flash && self.flash_enabled {
+                    self.write_flash(window, address, value);
+                }
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+                let physical_address = self.ram_offset(address);
+                self.ram[physical_address] = value;
+                self.dirty = true;
+            }
+            _ => {
+                panic!("invalid address to write to MBC6");
+            }
+        }
+    }
+}
+
+impl MBC for MBC6 {
+    // RAM followed by the whole flash chip
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.to_vec();
+        data.extend_from_slice(&self.flash);
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        if let Some(flash) = data.get(RAM_SIZE..) {
+            load_ram(&mut self.flash, flash);
+        }
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom_banks;
+
+    fn mbc6() -> MBC6 {
+        // number every 8KB half bank
+        let mut rom = vec![0u8; 0x20000];
+        for i in 0..0x10 {
+            rom[i * 0x2000] = i as u8;
+        }
+        MBC6::new(rom_banks(&rom))
+    }
+
+    fn flash_command(mbc6: &mut MBC6, command: u8) {
+        mbc6.write(0x2000, 0x02);
+        mbc6.write(0x5555, 0xAA);
+        mbc6.write(0x2000, 0x01);
+        mbc6.write(0x4AAA, 0x55);
+        mbc6.write(0x2000, 0x02);
+        mbc6.write(0x5555, command);
+    }
+
+    fn enable_flash(mbc6: &mut MBC6) {
+        mbc6.write(0x0C00, 0x01);
+        mbc6.write(0x1000, 0x01);
+        mbc6.write(0x2800, 0x08);
+    }
+
+    #[test]
+    fn test_independent_rom_windows() {
+        let mut mbc6 = mbc6();
+        mbc6.write(0x2000, 0x05);
+        mbc6.write(0x3000, 0x0C);
+        assert_eq!(mbc6.read(0x4000), 0x05);
+        assert_eq!(mbc6.read(0x6000), 0x0C);
+        assert_eq!(mbc6.read(0x0000), 0x00);
+    }
+
+    #[test]
+    fn test_ram_windows() {
+        let mut mbc6 = mbc6();
+        mbc6.write(0x0000, 0x0A);
+        mbc6.write(0x0400, 0x03);
+        mbc6.write(0x0800, 0x05);
+        mbc6.write(0xA000, 0x69);
+        mbc6.write(0xB000, 0x42);
+        mbc6.write(0x0800, 0x03);
+        assert_eq!(mbc6.read(0xB000), 0x69);
+        mbc6.write(0x0400, 0x05);
+        assert_eq!(mbc6.read(0xA000), 0x42);
+    }
+
+    #[test]
+    fn test_flash_program_and_erase() {
+        let mut mbc6 = mbc6();
+        enable_flash(&mut mbc6);
+
+        flash_command(&mut mbc6, 0xA0);
+        mbc6.write(0x2000, 0x10);
+        mbc6.write(0x4123, 0x69);
+        assert_eq!(mbc6.read(0x4123), 0x69);
+        assert!(mbc6.take_save_dirty());
+
+        flash_command(&mut mbc6, 0x80);
+        flash_command(&mut mbc6, 0x10);
+        mbc6.write(0x2000, 0x10);
+        assert_eq!(mbc6.read(0x4123), 0xFF);
+    }
+
+    #[test]
+    fn test_flash_write_protect() {
+        let mut mbc6 = mbc6();
+        enable_flash(&mut mbc6);
+        mbc6.write(0x1000, 0x00);
+
+        flash_command(&mut mbc6, 0xA0);
+        mbc6.write(0x4000, 0x00);
+        assert_eq!(mbc6.read(0x4000), 0xFF);
+        assert!(!mbc6.take_save_dirty());
+    }
+
+    #[test]
+    fn test_flash_identify() {
+        let mut mbc6 = mbc6();
+        enable_flash(&mut mbc6);
+        flash_command(&mut mbc6, 0x90);
+        assert_eq!(mbc6.read(0x4000), 0xC2);
+        assert_eq!(mbc6.read(0x4001), 0x81);
+
+        mbc6.write(0x4000, 0xF0);
+        assert_eq!(mbc6.read(0x4000), 0xFF);
+    }
+}
@@ -0,0 +1,428 @@
+use super::{ReadableMemory, WritableMemory, MBC};
+
+// accelerometer reading when the cart is held flat, and roughly how far 1g moves it
+const ACCELEROMETER_CENTER: f32 = 0x81D0 as f32;
+const ACCELEROMETER_ONE_G: f32 = 0x70 as f32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EepromState {
+    // waiting for the start bit
+    Idle,
+    // shifting in the 2 bit opcode and 8 bit address
+    Command,
+    // shifting out words, one bit per clock
+    Read { address: u8, bits_left: u8 },
+    // shifting in a word for WRITE, or for WRAL when address is None
+    Write { address: Option<u8>, bits: u8 },
+}
+
+/// The 93LC56 serial EEPROM, organised as 128 16-bit words.
+struct Eeprom {
+    words: [u16; 0x80],
+    write_enabled: bool,
+    chip_select: bool,
+    clock: bool,
+    data_out: bool,
+    shift: u16,
+    bits: u8,
+    state: EepromState,
+    dirty: bool,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            words: [0xFFFF; 0x80],
+            write_enabled: false,
+            chip_select: false,
+            clock: false,
+            data_out: true,
+            shift: 0,
+            bits: 0,
+            state: EepromState::Idle,
+            dirty: false,
+        }
+    }
+
+    // bit 7 CS, bit 6 CLK, bit 1 DI, bit 0 DO
+    fn read(&self) -> u8 {
+        let mut value = 0x3C;
+        if self.chip_select {
+            value |= 0x80;
+        }
+        if self.clock {
+            value |= 0x40;
+        }
+        if self.data_out {
+            value |= 0x01;
+        }
+        value
+    }
+
+    fn write(&mut self, value: u8) {
+        let chip_select = value & 0x80 == 0x80;
+        let clock = value & 0x40 == 0x40;
+        let data_in = value & 0x02 == 0x02;
+
+        if !chip_select {
+            // deselecting aborts whatever command was in flight
+            self.state = EepromState::Idle;
+            self.data_out = true;
+        } else if !self.clock && clock {
+            self.rising_edge(data_in);
+        }
+
+        self.chip_select = chip_select;
+        self.clock = clock;
+    }
+
+    fn rising_edge(&mut self, data_in: bool) {
+        match self.state {
+            EepromState::Idle => {
+                if data_in {
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+            EepromState::Command => {
+                self.shift = (self.shift << 1) | data_in as u16;
+                self.bits += 1;
+                if self.bits == 10 {
+                    self.execute(self.shift);
+                }
+            }
+            EepromState::Read { address, bits_left } => {
+                let word = self.words[address as usize];
+                self.data_out = (word >> (bits_left - 1)) & 0x01 == 0x01;
+                self.state = if bits_left == 1 {
+                    // reads carry on into the next word for as long as CS stays high
+                    EepromState::Read {
+                        address: (address + 1) & 0x7F,
+                        bits_left: 16,
+                    }
+                } else {
+                    EepromState::Read {
+                        address,
+                        bits_left: bits_left - 1,
+                    }
+                };
+            }
+            EepromState::Write { address, bits } => {
+                self.shift = (self.shift << 1) | data_in as u16;
+                if bits < 15 {
+                    self.state = EepromState::Write {
+                        address,
+                        bits: bits + 1,
+                    };
+                    return;
+                }
+
+                if self.write_enabled {
+                    match address {
+                        Some(address) => self.words[address as usize] = self.shift,
+                        None => self.words = [self.shift; 0x80],
+                    }
+                    self.dirty = true;
+                }
+                // writes complete instantly, so DO reports ready straight away
+                self.data_out = true;
+                self.state = EepromState::Idle;
+            }
+        }
+    }
+
+    fn execute(&mut self, command: u16) {
+        let opcode = (command >> 8) & 0x03;
+        let address = (command & 0x7F) as u8;
+        self.shift = 0;
+        self.state = EepromState::Idle;
+
+        match opcode {
+            0b10 => {
+                // READ, a dummy 0 comes out before the data
+                self.data_out = false;
+                self.state = EepromState::Read {
+                    address,
+                    bits_left: 16,
+                };
+            }
+            0b01 => {
+                self.state = EepromState::Write {
+                    address: Some(address),
+                    bits: 0,
+                };
+            }
+            0b11 => {
+                // ERASE
+                if self.write_enabled {
+                    self.words[address as usize] = 0xFFFF;
+                    self.dirty = true;
+                }
+                self.data_out = true;
+            }
+            _ => match (command >> 6) & 0x03 {
+                0b00 => self.write_enabled = false,
+                0b01 => {
+                    self.state = EepromState::Write {
+                        address: None,
+                        bits: 0,
+                    };
+                }
+                0b10 => {
+                    // ERAL
+                    if self.write_enabled {
+                        self.words = [0xFFFF; 0x80];
+                        self.dirty = true;
+                    }
+                    self.data_out = true;
+                }
+                _ => self.write_enabled = true,
+            },
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    fn load_bytes(&mut self, data: &[u8]) {
+        for (word, bytes) in self.words.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+}
+
+pub struct MBC7 {
+    rom_bank_select: u8,
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    ram_enabled: bool,
+    registers_enabled: bool,
+    tilt: (f32, f32),
+    latch_erased: bool,
+    x_latch: u16,
+    y_latch: u16,
+    eeprom: Eeprom,
+}
+
+impl MBC7 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> MBC7 {
+        MBC7 {
+            rom_bank_select: 1,
+            rom_banks,
+            ram_enabled: false,
+            registers_enabled: false,
+            tilt: (0.0, 0.0),
+            latch_erased: false,
+            x_latch: 0x8000,
+            y_latch: 0x8000,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn accelerometer(g: f32) -> u16 {
+        (ACCELEROMETER_CENTER + g.clamp(-2.0, 2.0) * ACCELEROMETER_ONE_G) as u16
+    }
+
+    fn read_register(&self, address: usize) -> u8 {
+        match (address >> 4) & 0x0F {
+            0x02 => self.x_latch as u8,
+            0x03 => (self.x_latch >> 8) as u8,
+            0x04 => self.y_latch as u8,
+            0x05 => (self.y_latch >> 8) as u8,
+            0x06 => 0x00,
+            0x08 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_register(&mut self, address: usize, value: u8) {
+        match (address >> 4) & 0x0F {
+            0x00 if value == 0x55 => {
+                self.latch_erased = true;
+                self.x_latch = 0x8000;
+                self.y_latch = 0x8000;
+            }
+            // only latches once after an erase, further 0xAA writes are ignored
+            0x01 if value == 0xAA && self.latch_erased => {
+                self.latch_erased = false;
+                self.x_latch = MBC7::accelerometer(self.tilt.0);
+                self.y_latch = MBC7::accelerometer(self.tilt.1);
+            }
+            0x08 => self.eeprom.write(value),
+            _ => {}
+        }
+    }
+}
+
+impl ReadableMemory for MBC7 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.rom_banks[0][address],
+            0x4000..=0x7FFF => {
+                let bank_select = self.rom_bank_select as usize % self.rom_banks.len();
+                self.rom_banks[bank_select][address & 0x3FFF]
+            }
+            0xA000..=0xAFFF if self.ram_enabled && self.registers_enabled => {
+                self.read_register(address)
+            }
+            0xA000..=0xBFFF => 0xFF,
+            _ => {
+                panic!("invalid address to read in MBC7, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for MBC7 {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value == 0x0A;
+            }
+            0x2000..=0x3FFF => {
+                self.rom_bank_select = value & 0x7F;
+            }
+            0x4000..=0x5FFF => {
+                // a second enable that has to be set before the registers show up
+                self.registers_enabled = value == 0x40;
+            }
+            0x6000..=0x7FFF => {}
+            0xA000..=0xAFFF if self.ram_enabled && self.registers_enabled => {
+                self.write_register(address, value);
+            }
+            0xA000..=0xBFFF => {}
+            _ => {
+                panic!("invalid address to write to MBC7");
+            }
+        }
+    }
+}
+
+impl MBC for MBC7 {
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.eeprom.to_bytes()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.eeprom.load_bytes(data);
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.eeprom.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom_banks;
+
+    const CS: u8 = 0x80;
+    const CLK: u8 = 0x40;
+    const DI: u8 = 0x02;
+
+    fn mbc7() -> MBC7 {
+        let mut mbc7 = MBC7::new(rom_banks(&[0u8; 0x8000]));
+        mbc7.write(0x0000, 0x0A);
+        mbc7.write(0x4000, 0x40);
+        mbc7
+    }
+
+    fn clock_bit(mbc7: &mut MBC7, bit: bool) -> bool {
+        let data_in = if bit { DI } else { 0 };
+        mbc7.write(0xA080, CS | data_in);
+        mbc7.write(0xA080, CS | CLK | data_in);
+        mbc7.read(0xA080) & 0x01 == 0x01
+    }
+
+    fn send(mbc7: &mut MBC7, value: u32, bits: u8) {
+        for i in (0..bits).rev() {
+            clock_bit(mbc7, (value >> i) & 0x01 == 0x01);
+        }
+    }
+
+    fn deselect(mbc7: &mut MBC7) {
+        mbc7.write(0xA080, 0x00);
+    }
+
+    fn read_word(mbc7: &mut MBC7, address: u8) -> u16 {
+        send(mbc7, 0b110 << 8 | address as u32, 11);
+        assert!(mbc7.read(0xA080) & 0x01 == 0x00, "missing dummy bit");
+        let mut word = 0u16;
+        for _ in 0..16 {
+            word = (word << 1) | clock_bit(mbc7, false) as u16;
+        }
+        deselect(mbc7);
+        word
+    }
+
+    #[test]
+    fn test_registers_need_both_enables() {
+        let mut mbc7 = MBC7::new(rom_banks(&[0u8; 0x8000]));
+        mbc7.write(0x0000, 0x0A);
+        assert_eq!(mbc7.read(0xA060), 0xFF);
+        mbc7.write(0x4000, 0x40);
+        assert_eq!(mbc7.read(0xA060), 0x00);
+    }
+
+    #[test]
+    fn test_accelerometer_latch() {
+        let mut mbc7 = mbc7();
+        mbc7.set_tilt(1.0, -0.5);
+        mbc7.write(0xA000, 0x55);
+        assert_eq!(mbc7.read(0xA030), 0x80);
+        mbc7.write(0xA010, 0xAA);
+        let x = mbc7.read(0xA020) as u16 | (mbc7.read(0xA030) as u16) << 8;
+        let y = mbc7.read(0xA040) as u16 | (mbc7.read(0xA050) as u16) << 8;
+        assert_eq!(x, 0x81D0 + 0x70);
+        assert_eq!(y, 0x81D0 - 0x38);
+
+        // no erase in between, so the old values stick
+        mbc7.set_tilt(0.0, 0.0);
+        mbc7.write(0xA010, 0xAA);
+        assert_eq!(mbc7.read(0xA020), 0x40);
+    }
+
+    #[test]
+    fn test_eeprom_write_protected_until_ewen() {
+        let mut mbc7 = mbc7();
+        send(&mut mbc7, 0b101 << 8 | 0x05, 11);
+        send(&mut mbc7, 0x1234, 16);
+        deselect(&mut mbc7);
+        assert_eq!(read_word(&mut mbc7, 0x05), 0xFFFF);
+        assert!(!mbc7.take_save_dirty());
+    }
+
+    #[test]
+    fn test_eeprom_write_and_read() {
+        let mut mbc7 = mbc7();
+        send(&mut mbc7, 0b100 << 8 | 0xC0, 11);
+        deselect(&mut mbc7);
+        send(&mut mbc7, 0b101 << 8 | 0x05, 11);
+        send(&mut mbc7, 0x1234, 16);
+        deselect(&mut mbc7);
+
+        assert!(mbc7.take_save_dirty());
+        assert_eq!(read_word(&mut mbc7, 0x05), 0x1234);
+        assert_eq!(mbc7.save_data()[0x0A..0x0C], [0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_eeprom_erase_all() {
+        let mut mbc7 = mbc7();
+        mbc7.load_save_data(&[0u8; 0x100]);
+        send(&mut mbc7, 0b100 << 8 | 0xC0, 11);
+        deselect(&mut mbc7);
+        send(&mut mbc7, 0b100 << 8 | 0x80, 11);
+        deselect(&mut mbc7);
+        assert_eq!(read_word(&mut mbc7, 0x7F), 0xFFFF);
+    }
+}
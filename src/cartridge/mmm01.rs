@@ -0,0 +1,203 @@
+use super::{load_ram, ram_offset, ReadableMemory, WritableMemory, MBC};
+
+/// MMM01 multicarts boot into a menu at the end of the ROM, which picks a game by
+/// setting the outer bank bits and then locks them so the game sees a normal MBC1.
+pub struct MMM01 {
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    locked: bool,
+    // bits 0-4 are the game's MBC1 style bank, bits 5-8 are the outer bank set by the menu
+    rom_bank_select: u16,
+    // low bits 1-4 frozen by the menu, so smaller games can be packed closer together
+    rom_bank_mask: u16,
+    ram_enabled: bool,
+    ram_bank_select: u8,
+    ram: Box<[u8]>,
+    ram_dirty: bool,
+}
+
+impl MMM01 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>, ram_size: usize) -> MMM01 {
+        MMM01 {
+            rom_banks,
+            locked: false,
+            rom_bank_select: 0,
+            rom_bank_mask: 0,
+            ram_enabled: false,
+            ram_bank_select: 0,
+            ram: vec![0u8; ram_size].into_boxed_slice(),
+            ram_dirty: false,
+        }
+    }
+
+    fn rom_bank(&self, bank: u16) -> &[u8; 0x4000] {
+        &self.rom_banks[bank as usize % self.rom_banks.len()]
+    }
+
+    // the game's bank bits 0-4, with 0 translated to 1 the same way MBC1 does
+    fn game_bank(&self) -> u16 {
+        let bank = self.rom_bank_select & 0x1F;
+        if bank & !self.rom_bank_mask & 0x1F == 0 {
+            bank | 0x01
+        } else {
+            bank
+        }
+    }
+
+    fn lower_bank(&self) -> u16 {
+        (self.rom_bank_select & !0x1F) | (self.rom_bank_select & self.rom_bank_mask)
+    }
+}
+
+impl ReadableMemory for MMM01 {
+    fn read(&self, address: usize) -> u8 {
+        if !self.locked {
+            // the menu lives in the last 32KB until the mapping is locked
+            let last = self.rom_banks.len() as u16;
+            return match address {
+                0x0000..=0x3FFF => self.rom_bank(last.wrapping_sub(2))[address],
+                0x4000..=0x7FFF => self.rom_bank(last.wrapping_sub(1))[address & 0x3FFF],
+                0xA000..=0xBFFF if self.ram_enabled && !self.ram.is_empty() => {
+                    self.ram[ram_offset(&self.ram, self.ram_bank_select, address)]
+                }
+                0xA000..=0xBFFF => 0xFF,
+                _ => panic!("invalid address to read in MMM01, this must be a programming error"),
+            };
+        }
+
+        match address {
+            0x0000..=0x3FFF => self.rom_bank(self.lower_bank())[address],
+            0x4000..=0x7FFF => {
+                let bank = self.lower_bank() | (self.game_bank() & !self.rom_bank_mask);
+                self.rom_bank(bank)[address & 0x3FFF]
+            }
+            0xA000..=0xBFFF if self.ram_enabled && !self.ram.is_empty() => {
+                self.ram[ram_offset(&self.ram, self.ram_bank_select, address)]
+            }
+            0xA000..=0xBFFF => 0xFF,
+            _ => panic!("invalid address to read in MMM01, this must be a programming error"),
+        }
+    }
+}
+
+impl WritableMemory for MMM01 {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if !self.locked && value & 0x40 == 0x40 {
+                    self.locked = true;
+                }
+            }
+            0x2000..=0x3FFF => {
+                let writable = if self.locked { 0x1F } else { 0x7F };
+                let bank = value as u16 & writable & !(self.rom_bank_mask & 0x1F);
+                let frozen = self.rom_bank_select & !writable;
+                let masked = self.rom_bank_select & self.rom_bank_mask & writable;
+                self.rom_bank_select = frozen | masked | bank;
+            }
+            0x4000..=0x5FFF => {
+                if self.locked {
+                    self.ram_bank_select = (self.ram_bank_select & 0x0C) | (value & 0x03);
+                } else {
+                    self.ram_bank_select = value & 0x0F;
+                    self.rom_bank_select =
+                        (self.rom_bank_select & 0x7F) | ((value as u16 & 0x30) << 3);
+                }
+            }
+            0x6000..=0x7FFF => {
+                if !self.locked {
+                    self.rom_bank_mask = (value as u16 & 0x3C) >> 1;
+                }
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled || self.ram.is_empty() {
+                    return;
+                }
+                let physical_address = ram_offset(&self.ram, self.ram_bank_select, address);
+                self.ram[physical_address] = value;
+                self.ram_dirty = true;
+            }
+            _ => panic!("invalid address to write to MMM01"),
+        }
+    }
+}
+
+impl MBC for MMM01 {
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom_banks;
+
+    fn mmm01(bank_count: usize) -> MMM01 {
+        let mut rom = vec![0u8; bank_count * 0x4000];
+        for i in 0..bank_count {
+            rom[i * 0x4000] = i as u8;
+        }
+        MMM01::new(rom_banks(&rom), 0x8000)
+    }
+
+    #[test]
+    fn test_menu_mapped_at_end_of_rom() {
+        let mmm01 = mmm01(0x40);
+        assert_eq!(mmm01.read(0x0000), 0x3E);
+        assert_eq!(mmm01.read(0x4000), 0x3F);
+    }
+
+    #[test]
+    fn test_lock_selects_outer_bank() {
+        let mut mmm01 = mmm01(0x40);
+        // game starts at bank 0x20
+        mmm01.write(0x2000, 0x20);
+        mmm01.write(0x0000, 0x40);
+        assert_eq!(mmm01.read(0x0000), 0x20);
+        assert_eq!(mmm01.read(0x4000), 0x21);
+
+        mmm01.write(0x2000, 0x05);
+        assert_eq!(mmm01.read(0x4000), 0x25);
+
+        // the outer bits can't be changed by the game any more
+        mmm01.write(0x2000, 0x7F);
+        assert_eq!(mmm01.read(0x4000), 0x3F);
+        mmm01.write(0x4000, 0x30);
+        assert_eq!(mmm01.read(0x0000), 0x20);
+    }
+
+    #[test]
+    fn test_rom_bank_mask_limits_game_banks() {
+        let mut mmm01 = mmm01(0x40);
+        // 64KB game at bank 0x08, only bit 0 and 1 belong to the game
+        mmm01.write(0x2000, 0x08);
+        mmm01.write(0x6000, 0x38);
+        mmm01.write(0x0000, 0x40);
+        assert_eq!(mmm01.read(0x0000), 0x08);
+
+        mmm01.write(0x2000, 0x1F);
+        assert_eq!(mmm01.read(0x4000), 0x0B);
+        mmm01.write(0x2000, 0x00);
+        assert_eq!(mmm01.read(0x4000), 0x09);
+    }
+
+    #[test]
+    fn test_ram_enable() {
+        let mut mmm01 = mmm01(4);
+        mmm01.write(0xA000, 0x69);
+        assert_eq!(mmm01.read(0xA000), 0xFF);
+        mmm01.write(0x0000, 0x0A);
+        mmm01.write(0xA000, 0x69);
+        assert_eq!(mmm01.read(0xA000), 0x69);
+        assert!(mmm01.take_save_dirty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::MBC;
+use crate::utility::file;
+
+/// How long to wait after the last write to save RAM before flushing it to disk.
+pub const DEFAULT_FLUSH_DELAY: Duration = Duration::from_secs(2);
+
+/// Keeps a battery-backed cartridge in sync with its `.sav` file.
+pub struct BatterySave {
+    path: PathBuf,
+    flush_delay: Duration,
+    last_write: Option<Instant>,
+}
+
+impl BatterySave {
+    pub fn new(path: PathBuf) -> BatterySave {
+        BatterySave {
+            path,
+            flush_delay: DEFAULT_FLUSH_DELAY,
+            last_write: None,
+        }
+    }
+
+    /// The save file sitting next to the ROM, `game.gb` saves to `game.sav`.
+    pub fn for_rom(rom_path: &Path) -> BatterySave {
+        BatterySave::new(rom_path.with_extension("sav"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load<T: MBC + ?Sized>(&self, mbc: &mut T) -> io::Result<()> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                mbc.load_save_data(&data);
+                Ok(())
+            }
+            // no save yet, the cartridge keeps its power-on RAM
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Picks up writes to save RAM and flushes once the game has stopped writing for a while.
+    pub fn update<T: MBC + ?Sized>(&mut self, mbc: &mut T) -> io::Result<()> {
+        if mbc.take_save_dirty() {
+            self.last_write = Some(Instant::now());
+        }
+
+        match self.last_write {
+            Some(last_write) if last_write.elapsed() >= self.flush_delay => self.flush(mbc),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes save RAM out if it changed since the last flush. Nothing is written
+    /// otherwise, so a RAM that never got loaded can't replace the file on disk.
+    pub fn flush<T: MBC + ?Sized>(&mut self, mbc: &mut T) -> io::Result<()> {
+        let dirty = mbc.take_save_dirty();
+        if !dirty && self.last_write.is_none() {
+            return Ok(());
+        }
+        self.last_write = None;
+        file::write_atomic(&self.path, &mbc.save_data())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{rom_banks, ReadableMemory, WritableMemory, MBC3};
+    use std::env;
+
+    fn save_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("gb-{}-{}.sav", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn mbc3() -> MBC3 {
+        let mut mbc3 = MBC3::new(rom_banks(&[0u8; 0x8000]), 0x2000);
+        mbc3.write(0x0000, 0x0A);
+        mbc3
+    }
+
+    #[test]
+    fn test_flush_and_load_round_trip() {
+        let path = save_path("round-trip");
+        let mut save = BatterySave::new(path.clone());
+        let mut mbc3 = mbc3();
+        mbc3.write(0xA123, 0x69);
+        save.flush(&mut mbc3).unwrap();
+
+        let mut loaded = self::mbc3();
+        save.load(&mut loaded).unwrap();
+        assert_eq!(loaded.read(0xA123), 0x69);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_update_waits_for_flush_delay() {
+        let path = save_path("delay");
+        let mut save = BatterySave {
+            flush_delay: Duration::from_secs(3600),
+            ..BatterySave::new(path.clone())
+        };
+        let mut mbc3 = mbc3();
+        mbc3.write(0xA000, 0x69);
+        save.update(&mut mbc3).unwrap();
+        assert!(!path.exists());
+
+        save.flush_delay = Duration::ZERO;
+        save.update(&mut mbc3).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[0], 0x69);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_flush_without_writes_keeps_file() {
+        let path = save_path("untouched");
+        fs::write(&path, [0x42; 0x2000]).unwrap();
+        let mut save = BatterySave::new(path.clone());
+        save.flush(&mut mbc3()).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[0], 0x42);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_save_is_not_an_error() {
+        let save = BatterySave::new(save_path("missing"));
+        assert!(save.load(&mut mbc3()).is_ok());
+    }
+}
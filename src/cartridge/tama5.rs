@@ -0,0 +1,243 @@
+use super::{unix_time, ReadableMemory, WritableMemory, MBC};
+
+const RAM_SIZE: usize = 0x20;
+
+/// Bandai's TAMA5, used by Tamagotchi 3. Everything goes through a register
+/// select at 0xA001 and a 4 bit data port at 0xA000, backed by 32 bytes of
+/// battery RAM and a real time clock.
+pub struct TAMA5 {
+    rom_bank_select: u8,
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    register_select: u8,
+    data_in: u8,
+    data_out: u8,
+    command: u8,
+    address: u8,
+    ram: [u8; RAM_SIZE],
+    // unix time at which the clock read 0 days 00:00:00, the clock runs off wall time
+    rtc_epoch: u64,
+    dirty: bool,
+    clock: fn() -> u64,
+}
+
+impl TAMA5 {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> TAMA5 {
+        let clock: fn() -> u64 = unix_time;
+        TAMA5 {
+            rom_bank_select: 0,
+            rom_banks,
+            register_select: 0,
+            data_in: 0,
+            data_out: 0,
+            command: 0,
+            address: 0,
+            ram: [0u8; RAM_SIZE],
+            rtc_epoch: clock(),
+            dirty: false,
+            clock,
+        }
+    }
+
+    // clock registers in the TC8521 layout, a BCD digit each
+    fn rtc_digits(&self) -> [u8; 0x0D] {
+        let seconds = (self.clock)().saturating_sub(self.rtc_epoch);
+        let (second, minute) = (seconds % 60, (seconds / 60) % 60);
+        let (hour, day) = ((seconds / 3600) % 24, (seconds / 86400) % 100);
+        let bcd = |value: u64| [(value % 10) as u8, (value / 10) as u8];
+        let mut digits = [0u8; 0x0D];
+        digits[0x00..0x02].copy_from_slice(&bcd(second));
+        digits[0x02..0x04].copy_from_slice(&bcd(minute));
+        digits[0x04..0x06].copy_from_slice(&bcd(hour));
+        digits[0x06] = (seconds / 86400 % 7) as u8;
+        digits[0x07..0x09].copy_from_slice(&bcd(day));
+        digits
+    }
+
+    fn write_rtc_digit(&mut self, register: u8, value: u8) {
+        let mut digits = self.rtc_digits();
+        if let Some(digit) = digits.get_mut(register as usize) {
+            *digit = value & 0x0F;
+        }
+        let number = |i: usize| (digits[i] + digits[i + 1] * 10) as u64;
+        let seconds = number(0) + number(2) * 60 + number(4) * 3600 + number(7) * 86400;
+        self.rtc_epoch = (self.clock)().saturating_sub(seconds);
+        self.dirty = true;
+    }
+
+    fn execute(&mut self) {
+        let address = self.address as usize & 0x1F;
+        match self.command {
+            0x00 => {
+                self.ram[address] = self.data_in;
+                self.dirty = true;
+            }
+            0x01 => self.data_out = self.ram[address],
+            0x02 => self.write_rtc_digit(self.address & 0x0F, self.data_in),
+            0x04 => {
+                self.data_out = *self
+                    .rtc_digits()
+                    .get(self.address as usize & 0x0F)
+                    .unwrap_or(&0)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ReadableMemory for TAMA5 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.rom_banks[0][address],
+            0x4000..=0x7FFF => {
+                let bank_select = self.rom_bank_select as usize % self.rom_banks.len();
+                self.rom_banks[bank_select][address & 0x3FFF]
+            }
+            0xA000 => match self.register_select {
+                // the game checks for this after selecting register 0x0A to unlock the chip
+                0x0A => 0xF1,
+                0x0C => 0xF0 | (self.data_out & 0x0F),
+                0x0D => 0xF0 | (self.data_out >> 4),
+                _ => 0xFF,
+            },
+            0xA001..=0xBFFF => 0xFF,
+            _ => {
+                panic!("invalid address to read in TAMA5, this must be a programming error");
+            }
+        }
+    }
+}
+
+impl WritableMemory for TAMA5 {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x7FFF => {}
+            0xA000 => {
+                let value = value & 0x0F;
+                match self.register_select {
+                    0x00 => self.rom_bank_select = (self.rom_bank_select & 0x10) | value,
+                    0x01 => {
+                        self.rom_bank_select = (self.rom_bank_select & 0x0F) | (value & 0x01) << 4
+                    }
+                    0x04 => self.data_in = (self.data_in & 0xF0) | value,
+                    0x05 => self.data_in = (self.data_in & 0x0F) | value << 4,
+                    0x06 => {
+                        self.address = (self.address & 0x0F) | (value & 0x01) << 4;
+                        self.command = value >> 1;
+                    }
+                    0x07 => {
+                        // the low address nibble goes last and kicks off the command
+                        self.address = (self.address & 0x10) | value;
+                        self.execute();
+                    }
+                    _ => {}
+                }
+            }
+            0xA001 => self.register_select = value & 0x0F,
+            0xA002..=0xBFFF => {}
+            _ => {
+                panic!("invalid address to write to TAMA5");
+            }
+        }
+    }
+}
+
+impl MBC for TAMA5 {
+    // RAM followed by the clock epoch
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.to_vec();
+        data.extend_from_slice(&self.rtc_epoch.to_le_bytes());
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let length = RAM_SIZE.min(data.len());
+        self.ram[..length].copy_from_slice(&data[..length]);
+        if let Some(epoch) = data.get(RAM_SIZE..RAM_SIZE + 8) {
+            self.rtc_epoch = u64::from_le_bytes(epoch.try_into().unwrap());
+        }
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::rom_banks;
+
+    fn tama5(now: fn() -> u64) -> TAMA5 {
+        let mut rom = vec![0u8; 0x20 * 0x4000];
+        for i in 0..0x20 {
+            rom[i * 0x4000] = i as u8;
+        }
+        TAMA5 {
+            clock: now,
+            rtc_epoch: now(),
+            ..TAMA5::new(rom_banks(&rom))
+        }
+    }
+
+    fn write_register(tama5: &mut TAMA5, register: u8, value: u8) {
+        tama5.write(0xA001, register);
+        tama5.write(0xA000, value);
+    }
+
+    fn command(tama5: &mut TAMA5, command: u8, address: u8, data: u8) -> u8 {
+        write_register(tama5, 0x04, data & 0x0F);
+        write_register(tama5, 0x05, data >> 4);
+        write_register(tama5, 0x06, command << 1 | address >> 4);
+        write_register(tama5, 0x07, address & 0x0F);
+        tama5.write(0xA001, 0x0C);
+        let low = tama5.read(0xA000) & 0x0F;
+        tama5.write(0xA001, 0x0D);
+        let high = tama5.read(0xA000) & 0x0F;
+        high << 4 | low
+    }
+
+    #[test]
+    fn test_unlock_handshake() {
+        let mut tama5 = tama5(|| 0);
+        tama5.write(0xA001, 0x0A);
+        assert_eq!(tama5.read(0xA000), 0xF1);
+    }
+
+    #[test]
+    fn test_rom_banking() {
+        let mut tama5 = tama5(|| 0);
+        write_register(&mut tama5, 0x00, 0x03);
+        write_register(&mut tama5, 0x01, 0x01);
+        assert_eq!(tama5.read(0x4000), 0x13);
+
+        write_register(&mut tama5, 0x01, 0x00);
+        write_register(&mut tama5, 0x00, 0x00);
+        assert_eq!(tama5.read(0x4000), 0x00);
+    }
+
+    #[test]
+    fn test_ram_write_and_read() {
+        let mut tama5 = tama5(|| 0);
+        command(&mut tama5, 0x00, 0x15, 0x69);
+        assert!(tama5.take_save_dirty());
+        assert_eq!(command(&mut tama5, 0x01, 0x15, 0x00), 0x69);
+        assert_eq!(tama5.save_data()[0x15], 0x69);
+    }
+
+    #[test]
+    fn test_rtc_read() {
+        // 1 day, 13:42:07
+        let mut tama5 = tama5(|| 86400 + 13 * 3600 + 42 * 60 + 7);
+        tama5.rtc_epoch = 0;
+        let digits: Vec<u8> = (0..9).map(|i| command(&mut tama5, 0x04, i, 0)).collect();
+        assert_eq!(digits, [7, 0, 2, 4, 3, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_rtc_set() {
+        let mut tama5 = tama5(|| 1_000_000);
+        command(&mut tama5, 0x02, 0x05, 0x02);
+        assert_eq!(command(&mut tama5, 0x04, 0x05, 0), 0x02);
+        assert!(tama5.take_save_dirty());
+    }
+}
@@ -0,0 +1,357 @@
+use std::cell::Cell;
+
+use super::{Mapper, ReadableMemory, WritableMemory, MBC, NINTENDO_LOGO};
+
+/// Wisdom Tree carts switch the whole 32KB window at once, taking the bank number
+/// from the low byte of the address written to rather than the value.
+pub struct WisdomTree {
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    rom_bank_select: usize,
+}
+
+impl WisdomTree {
+    pub fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> WisdomTree {
+        WisdomTree {
+            rom_banks,
+            rom_bank_select: 0,
+        }
+    }
+}
+
+impl ReadableMemory for WisdomTree {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x7FFF => {
+                let bank = (self.rom_bank_select * 2 + (address >> 14)) % self.rom_banks.len();
+                self.rom_banks[bank][address & 0x3FFF]
+            }
+            // no RAM on these boards
+            0xA000..=0xBFFF => 0xFF,
+            _ => panic!("invalid address to read in WisdomTree, this must be a programming error"),
+        }
+    }
+}
+
+impl WritableMemory for WisdomTree {
+    fn write(&mut self, address: usize, _value: u8) {
+        match address {
+            0x0000..=0x3FFF => self.rom_bank_select = address & 0xFF,
+            0x4000..=0x7FFF | 0xA000..=0xBFFF => {}
+            _ => panic!("invalid address to write to WisdomTree"),
+        }
+    }
+}
+
+impl MBC for WisdomTree {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SachenLock {
+    // MMC2 only, the CGB boot ROM gets to see the logo as it really is
+    Cgb,
+    Locked,
+    Unlocked,
+}
+
+/// Sachen MMC1/MMC2 keep their own logo in the header and scramble the address lines
+/// while the boot ROM runs, so the logo check sees Nintendo's instead.
+pub struct Sachen {
+    rom_banks: Box<[Box<[u8; 0x4000]>]>,
+    base_bank: u8,
+    rom_bank_select: u8,
+    rom_bank_mask: u8,
+    // reads have to be able to unlock the cart, hence the Cell
+    lock: Cell<SachenLock>,
+}
+
+impl Sachen {
+    pub fn new_mmc1(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> Sachen {
+        Sachen::new(rom_banks, SachenLock::Locked)
+    }
+
+    pub fn new_mmc2(rom_banks: Box<[Box<[u8; 0x4000]>]>) -> Sachen {
+        Sachen::new(rom_banks, SachenLock::Cgb)
+    }
+
+    fn new(rom_banks: Box<[Box<[u8; 0x4000]>]>, lock: SachenLock) -> Sachen {
+        Sachen {
+            rom_banks,
+            base_bank: 0,
+            rom_bank_select: 1,
+            rom_bank_mask: 0,
+            lock: Cell::new(lock),
+        }
+    }
+
+    fn rom_bank(&self, bank: u8) -> &[u8; 0x4000] {
+        &self.rom_banks[bank as usize % self.rom_banks.len()]
+    }
+
+    // the boot ROM never touches 0x0100, so the first read of the entry point means it's done
+    fn track_lock(&self, address: usize) {
+        match (self.lock.get(), address) {
+            (SachenLock::Unlocked, _) => {}
+            (_, 0x0100) => self.lock.set(SachenLock::Unlocked),
+            (SachenLock::Cgb, 0x0133) => self.lock.set(SachenLock::Locked),
+            _ => {}
+        }
+    }
+}
+
+// swaps A0 with A6 and A1 with A4
+fn scramble(address: usize) -> usize {
+    let bit = |n: usize| (address >> n) & 1;
+    (address & !0x53) | (bit(6)) | (bit(4) << 1) | (bit(1) << 4) | (bit(0) << 6)
+}
+
+impl ReadableMemory for Sachen {
+    fn read(&self, address: usize) -> u8 {
+        let scrambled = self.lock.get() == SachenLock::Locked && address & 0xFF00 == 0x0100;
+        self.track_lock(address);
+
+        match address {
+            0x0000..=0x3FFF => {
+                let address = if scrambled {
+                    scramble(address)
+                } else {
+                    address
+                };
+                self.rom_bank(self.base_bank & self.rom_bank_mask)[address]
+            }
+            0x4000..=0x7FFF => {
+                let bank = (self.base_bank & self.rom_bank_mask)
+                    | (self.rom_bank_select & !self.rom_bank_mask);
+                self.rom_bank(bank)[address & 0x3FFF]
+            }
+            0xA000..=0xBFFF => 0xFF,
+            _ => panic!("invalid address to read in Sachen, this must be a programming error"),
+        }
+    }
+}
+
+impl WritableMemory for Sachen {
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                // the base can only move while the game is in the top of its bank range
+                if self.rom_bank_select & 0x30 == 0x30 {
+                    self.base_bank = value;
+                }
+            }
+            0x2000..=0x3FFF => self.rom_bank_select = if value == 0 { 1 } else { value },
+            0x4000..=0x5FFF => self.rom_bank_mask = value,
+            0x6000..=0x7FFF | 0xA000..=0xBFFF => {}
+            _ => panic!("invalid address to write to Sachen"),
+        }
+    }
+}
+
+impl MBC for Sachen {}
+
+/// Bootleg MBC1/MBC5 clones often decode the bank register somewhere other than
+/// 0x2000, so writes there get moved to where the real chip expects them.
+pub struct Bootleg<M: MBC> {
+    inner: M,
+    bank_register: usize,
+}
+
+impl<M: MBC> Bootleg<M> {
+    pub fn new(inner: M, bank_register: u16) -> Bootleg<M> {
+        Bootleg {
+            inner,
+            bank_register: bank_register as usize,
+        }
+    }
+}
+
+impl<M: MBC> ReadableMemory for Bootleg<M> {
+    fn read(&self, address: usize) -> u8 {
+        self.inner.read(address)
+    }
+}
+
+impl<M: MBC> WritableMemory for Bootleg<M> {
+    fn write(&mut self, address: usize, value: u8) {
+        // registers are only decoded on the top address lines, like the chips they copy
+        if address < 0x8000 && address & 0xF000 == self.bank_register & 0xF000 {
+            self.inner.write(0x2000, value);
+        } else {
+            self.inner.write(address, value);
+        }
+    }
+}
+
+impl<M: MBC> MBC for Bootleg<M> {
+    fn tick(&mut self, ticks: u64) {
+        self.inner.tick(ticks)
+    }
+
+    fn rumble(&self) -> bool {
+        self.inner.rumble()
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.inner.save_data()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        self.inner.load_save_data(data)
+    }
+
+    fn take_save_dirty(&mut self) -> bool {
+        self.inner.take_save_dirty()
+    }
+}
+
+/// Spots unlicensed hardware from signatures in the header. Returns None when the
+/// cart looks like what its header says. Bootleg boards leave no such trace and have
+/// to be picked with `--mapper`.
+pub fn detect_unlicensed(rom: &[u8]) -> Option<Mapper> {
+    if rom.len() < 0x0150 {
+        return None;
+    }
+
+    let cartridge_type = rom[0x0147];
+    let logo = &rom[0x0104..0x0134];
+    let scrambled_logo = (0x0104..0x0134).map(|address| rom[scramble(address)]);
+    if logo != NINTENDO_LOGO && scrambled_logo.eq(NINTENDO_LOGO) {
+        // MMC2 was made for the carts that also run in colour
+        return if rom[0x0143] & 0x80 != 0 {
+            Some(Mapper::SachenMMC2)
+        } else {
+            Some(Mapper::SachenMMC1)
+        };
+    }
+
+    // their games put the company name in the title, plain ROM-only carts don't
+    let title = &rom[0x0134..0x0144];
+    let wisdom_tree = title.windows(6).any(|window| window == b"WISDOM");
+    if cartridge_type == 0xC0 || (cartridge_type == 0x00 && wisdom_tree) {
+        return Some(Mapper::WisdomTree);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{rom_banks, MBC1, MBC5};
+
+    fn numbered_rom(count: usize) -> Vec<u8> {
+        let mut rom = vec![0u8; count * 0x4000];
+        for i in 0..count {
+            rom[i * 0x4000 + 0x0200] = i as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_wisdom_tree_switches_32kb_by_address() {
+        let mut cart = WisdomTree::new(rom_banks(&numbered_rom(8)));
+        assert_eq!(cart.read(0x0200), 0);
+        assert_eq!(cart.read(0x4200), 1);
+
+        // the value is ignored, only the address counts
+        cart.write(0x0002, 0xFF);
+        assert_eq!(cart.read(0x0200), 4);
+        assert_eq!(cart.read(0x4200), 5);
+    }
+
+    #[test]
+    fn test_scramble() {
+        assert_eq!(scramble(0x0101), 0x0140);
+        assert_eq!(scramble(0x0102), 0x0110);
+        assert_eq!(scramble(0x0133), 0x0172);
+        assert_eq!(scramble(scramble(0x0127)), 0x0127);
+    }
+
+    fn sachen_rom() -> Vec<u8> {
+        let mut rom = numbered_rom(0x40);
+        for (i, byte) in NINTENDO_LOGO.iter().enumerate() {
+            rom[scramble(0x0104 + i)] = *byte;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_sachen_mmc1_unscrambles_logo_until_entry_point() {
+        let rom = sachen_rom();
+        let cart = Sachen::new_mmc1(rom_banks(&rom));
+        assert_eq!(cart.read(0x0105), NINTENDO_LOGO[1]);
+        cart.read(0x0100);
+        assert_eq!(cart.read(0x0105), rom[0x0105]);
+    }
+
+    #[test]
+    fn test_sachen_mmc2_plain_logo_for_cgb_pass() {
+        let rom = sachen_rom();
+        let cart = Sachen::new_mmc2(rom_banks(&rom));
+        assert_eq!(cart.read(0x0105), rom[0x0105]);
+        cart.read(0x0133);
+        assert_eq!(cart.read(0x0105), NINTENDO_LOGO[1]);
+        cart.read(0x0100);
+        assert_eq!(cart.read(0x0105), rom[0x0105]);
+    }
+
+    #[test]
+    fn test_sachen_banking() {
+        let mut cart = Sachen::new_mmc1(rom_banks(&sachen_rom()));
+        cart.write(0x2000, 0x05);
+        assert_eq!(cart.read(0x4200), 0x05);
+
+        // base bank only moves with the upper bits set
+        cart.write(0x0000, 0x20);
+        cart.write(0x4000, 0x30);
+        assert_eq!(cart.read(0x0200), 0x00);
+        cart.write(0x2000, 0x35);
+        cart.write(0x0000, 0x20);
+        assert_eq!(cart.read(0x0200), 0x20);
+        cart.write(0x2000, 0x03);
+        assert_eq!(cart.read(0x4200), 0x23);
+    }
+
+    #[test]
+    fn test_bootleg_moves_bank_register() {
+        let banks = rom_banks(&numbered_rom(8));
+        let mut cart = Bootleg::new(MBC5::new(banks, 0, false), 0x7000);
+        cart.write(0x7123, 0x03);
+        assert_eq!(cart.read(0x4200), 0x03);
+        cart.write(0x2000, 0x05);
+        assert_eq!(cart.read(0x4200), 0x05);
+    }
+
+    #[test]
+    fn test_bootleg_mbc1_keeps_mbc1_banking() {
+        let banks = rom_banks(&numbered_rom(0x40));
+        let mut cart = Bootleg::new(MBC1::new(banks, 0), 0x7000);
+        cart.write(0x7000, 0x03);
+        assert_eq!(cart.read(0x4200), 0x03);
+        // bank 0 still maps to 1, and the upper bits come from 0x4000 like on MBC1
+        cart.write(0x7000, 0x00);
+        assert_eq!(cart.read(0x4200), 0x01);
+        cart.write(0x4000, 0x01);
+        assert_eq!(cart.read(0x4200), 0x21);
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut rom = sachen_rom();
+        assert_eq!(detect_unlicensed(&rom), Some(Mapper::SachenMMC1));
+        rom[0x0143] = 0x80;
+        assert_eq!(detect_unlicensed(&rom), Some(Mapper::SachenMMC2));
+
+        // a big ROM-only image is just a bad dump, not a Wisdom Tree cart
+        let mut rom = numbered_rom(4);
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        assert_eq!(detect_unlicensed(&rom), None);
+        rom[0x0134..0x0140].copy_from_slice(b"WISDOM TREE\0");
+        assert_eq!(detect_unlicensed(&rom), Some(Mapper::WisdomTree));
+
+        // licensed MBC1 games write 0x6000 all the time
+        let mut rom = numbered_rom(4);
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x0147] = 0x01;
+        rom[0x0300..0x0306].copy_from_slice(&[0xEA, 0x00, 0x60, 0xEA, 0x00, 0x60]);
+        assert_eq!(detect_unlicensed(&rom), None);
+    }
+}
@@ -0,0 +1,150 @@
+use std::{fmt, fs, io, path::Path};
+
+#[derive(Debug)]
+pub enum ColourSchemeError {
+    Io(io::Error),
+    Colour(String),
+    Count(usize),
+}
+
+impl fmt::Display for ColourSchemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColourSchemeError::Io(error) => write!(f, "{}", error),
+            ColourSchemeError::Colour(colour) => {
+                write!(f, "{} isn't a colour, expected RRGGBB in hex", colour)
+            }
+            ColourSchemeError::Count(count) => {
+                write!(
+                    f,
+                    "found {} colours, expected one for each of the 4 shades",
+                    count
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ColourSchemeError {
+    fn from(error: io::Error) -> Self {
+        ColourSchemeError::Io(error)
+    }
+}
+
+/// RGB for each of the four shades in the framebuffer, lightest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColourScheme {
+    colours: [[u8; 3]; 4],
+}
+
+impl ColourScheme {
+    pub const DMG: ColourScheme = ColourScheme {
+        colours: [
+            [0x9B, 0xBC, 0x0F],
+            [0x8B, 0xAC, 0x0F],
+            [0x30, 0x62, 0x30],
+            [0x0F, 0x38, 0x0F],
+        ],
+    };
+    pub const POCKET: ColourScheme = ColourScheme {
+        colours: [
+            [0xC4, 0xCF, 0xA1],
+            [0x8B, 0x95, 0x6D],
+            [0x4D, 0x53, 0x3C],
+            [0x1F, 0x1F, 0x1F],
+        ],
+    };
+    pub const LIGHT: ColourScheme = ColourScheme {
+        colours: [
+            [0x00, 0xB5, 0x81],
+            [0x00, 0x9A, 0x71],
+            [0x00, 0x69, 0x4A],
+            [0x00, 0x4F, 0x3B],
+        ],
+    };
+
+    pub fn from_name(name: &str) -> Option<ColourScheme> {
+        match name {
+            "dmg" => Some(ColourScheme::DMG),
+            "pocket" => Some(ColourScheme::POCKET),
+            "light" => Some(ColourScheme::LIGHT),
+            _ => None,
+        }
+    }
+
+    /// Four colours like `#9BBC0F`, separated by whitespace or commas, lightest first.
+    pub fn parse(text: &str) -> Result<ColourScheme, ColourSchemeError> {
+        let colours = text
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .map(parse_colour)
+            .collect::<Result<Vec<_>, _>>()?;
+        let colours: [[u8; 3]; 4] = colours
+            .as_slice()
+            .try_into()
+            .map_err(|_| ColourSchemeError::Count(colours.len()))?;
+        Ok(ColourScheme { colours })
+    }
+
+    pub fn from_file(path: &Path) -> Result<ColourScheme, ColourSchemeError> {
+        ColourScheme::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn colour(&self, shade: u8) -> [u8; 3] {
+        self.colours[(shade & 0x03) as usize]
+    }
+
+    /// Turns a buffer of shades into packed RGB, three bytes a pixel.
+    pub fn resolve(&self, shades: &[u8]) -> Vec<u8> {
+        shades
+            .iter()
+            .flat_map(|shade| self.colour(*shade))
+            .collect()
+    }
+}
+
+impl Default for ColourScheme {
+    fn default() -> Self {
+        ColourScheme::DMG
+    }
+}
+
+fn parse_colour(token: &str) -> Result<[u8; 3], ColourSchemeError> {
+    let hex = token.strip_prefix('#').unwrap_or(token);
+    let value = match hex.len() {
+        6 => u32::from_str_radix(hex, 16).ok(),
+        _ => None,
+    };
+    let Some(value) = value else {
+        return Err(ColourSchemeError::Colour(token.to_string()));
+    };
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let scheme = ColourScheme::parse("#FFFFFF, aaaaaa\n#555555\n000000\n").unwrap();
+        assert_eq!(scheme.colour(0), [0xFF, 0xFF, 0xFF]);
+        assert_eq!(scheme.colour(1), [0xAA, 0xAA, 0xAA]);
+        assert_eq!(scheme.colour(3), [0x00, 0x00, 0x00]);
+
+        assert!(matches!(
+            ColourScheme::parse("#FFFFFF #000000"),
+            Err(ColourSchemeError::Count(2))
+        ));
+        assert!(matches!(
+            ColourScheme::parse("#FFFFFF #000000 #GGGGGG #123"),
+            Err(ColourSchemeError::Colour(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let rgb = ColourScheme::POCKET.resolve(&[0, 3]);
+        assert_eq!(rgb, vec![0xC4, 0xCF, 0xA1, 0x1F, 0x1F, 0x1F]);
+    }
+}
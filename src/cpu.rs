@@ -1,7 +1,9 @@
 use crate::{
+    boot,
     cartridge::MBC,
     interrupts::Interrupts,
     mmu::MMU,
+    model::Model,
     opcodes::{ExtendedOpcode, Opcode},
     registers::Registers,
 };
@@ -56,6 +58,22 @@ impl<'a, T: MBC> CPU<'a, T> {
         }
     }
 
+    pub fn model(&self) -> Model {
+        self.mmu.model()
+    }
+
+    /// Starts at 0x0100 with the state the boot ROM would have left.
+    pub fn skip_boot(&mut self) {
+        let header: Vec<u8> = (0..0x0150).map(|address| self.mmu.read(address)).collect();
+        self.registers = boot::registers(self.model(), &header);
+        self.mmu.skip_boot();
+    }
+
+    /// Starts at 0x0000, for when a boot ROM has been mapped in.
+    pub fn power_on(&mut self) {
+        self.registers = boot::power_on_registers();
+    }
+
     pub fn exec_next_instruction(&mut self) -> u64 {
         self.ticks = 0;
         let pc = self.registers.pc;
@@ -64,17 +82,9 @@ impl<'a, T: MBC> CPU<'a, T> {
         self.ticks
     }
 
-    pub fn request_interrupt(&self, interrupt: Interrupt) {
-        if !self.interrupt_enabled {
-            return;
-        }
-
-        // match interrupt {
-        //   Interrupt::Joypad
-        //     Interrupt::VBlank => todo!(),
-        //     Interrupt::LCD => todo!(),
-        //     Interrupt::Timer => todo!(),
-        // }
+    // IF is set whether or not interrupts are enabled, IME only gates dispatch
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.mmu.request_interrupt(interrupt);
     }
 
     fn push_pc(&mut self) {
@@ -844,6 +854,7 @@ impl<'a, T: MBC> CPU<'a, T> {
                     T: MBC,
                 {
                     let res = wide!(cpu.registers, $dest_hi, $dest_lo);
+                    cpu.mmu.corrupt_oam(res);
                     let (res, _) = res.overflowing_add(1);
                     wide!(cpu.registers, $dest_hi, $dest_lo, res, cpu);
                 }
@@ -1622,9 +1633,10 @@ impl<'a, T: MBC> CPU<'a, T> {
                 where
                     T: MBC,
                 {
-                    let res = wide!(cpu.registers, b, c);
+                    let res = wide!(cpu.registers, $src_hi, $src_lo);
+                    cpu.mmu.corrupt_oam(res);
                     let (res, _) = res.overflowing_sub(1);
-                    wide!(cpu.registers, b, c, res, cpu);
+                    wide!(cpu.registers, $src_hi, $src_lo, res, cpu);
                 }
                 eval
             }};
@@ -2553,6 +2565,8 @@ mod tests {
         let mut mmu = MMU::<MBC3>::new_with_mbc3(&mut gpu);
         mmu.write(0x0000, 0x34);
         mmu.write(0x0001, 0xA2);
+        // those land on the MBC3 RAM enable register, turn RAM back on for the store
+        mmu.write(0x0000, 0x0A);
 
         let mut cpu = CPU {
             registers: Registers {
@@ -2953,6 +2967,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_dec_de_and_hl_leave_bc_alone() {
+        let mut gpu = VRAM::new();
+        let mut mmu = MMU::<MBC3>::new_with_mbc3(&mut gpu);
+        let mut cpu = CPU {
+            registers: Registers {
+                pc: 0x00,
+                b: 0x12,
+                c: 0x34,
+                d: 0xA2,
+                e: 0x00,
+                h: 0x10,
+                l: 0x01,
+                ..Registers::new()
+            },
+            ..CPU::<MBC3>::new(&mut mmu)
+        };
+
+        cpu.call(0x1B);
+        assert_eq!((cpu.registers.d, cpu.registers.e), (0xA1, 0xFF));
+        cpu.call(0x2B);
+        assert_eq!((cpu.registers.h, cpu.registers.l), (0x10, 0x00));
+        assert_eq!((cpu.registers.b, cpu.registers.c), (0x12, 0x34));
+    }
+
     #[test]
     fn test_jr_i8() {
         let mut gpu = VRAM::new();
@@ -4697,9 +4736,10 @@ mod tests {
     fn test_set_4_hl() {
         let mut gpu = VRAM::new();
         let rom_banks = Box::new([(); 0x80].map(|_| Box::new([0u8; 0x4000])));
-        let mbc3 = MBC3::new(rom_banks);
+        let mbc3 = MBC3::new(rom_banks, 0x8000);
         let mut gpu = VRAM::new();
         let mut mmu = MMU::new(&mut gpu, mbc3);
+        mmu.write(0x0000, 0x0A);
         mmu.write(0xA234, 0x00);
         let mut cpu = CPU {
             registers: Registers {
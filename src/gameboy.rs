@@ -1,42 +1,259 @@
 use crate::{
-    cartridge::MBC,
+    cartridge::{BatterySave, CameraSource, MBC},
+    colour_scheme::ColourScheme,
     cpu::{Interrupt, CPU},
-    gpu::{GpuEvent, VRAM},
-    utility::ui_state::UIState,
+    gpu::{oam_json, GpuEvent, DOTS_PER_LINE, LINES_PER_FRAME, SCREEN_HEIGHT, SCREEN_WIDTH},
+    image::Image,
+    recorder::Recorder,
+    utility::{file::write_atomic, ui_state::UIState},
 };
+use std::{fs, io, path::Path};
 
 pub struct Gameboy<'a, T>
 where
     T: MBC,
 {
     cpu: CPU<'a, T>,
-    gpu: VRAM,
     ui_state: UIState,
     ui_changed: bool,
+    battery_save: Option<BatterySave>,
+    colour_scheme: ColourScheme,
+    recorder: Option<Recorder>,
+    // time since the last VBlank, so frames keep coming while the LCD is off
+    dots_since_frame: u64,
 }
 
 impl<'a, T: MBC> Gameboy<'a, T> {
+    pub fn new(cpu: CPU<'a, T>, battery_save: Option<BatterySave>) -> Gameboy<'a, T> {
+        Gameboy {
+            cpu,
+            ui_state: UIState::new(),
+            ui_changed: false,
+            battery_save,
+            colour_scheme: ColourScheme::default(),
+            recorder: None,
+            dots_since_frame: 0,
+        }
+    }
+
     pub fn go(&mut self, ui_state: Option<UIState>) -> u64 {
         let ticks = self.cpu.exec_next_instruction();
-        let gpu_event = self.gpu.go(ticks);
-
-        match gpu_event {
-            GpuEvent::LCD => {
-                self.cpu.request_interrupt(Interrupt::LCD);
+        self.dots_since_frame += ticks;
+        for gpu_event in self.cpu.mmu.tick(ticks) {
+            match gpu_event {
+                GpuEvent::LCD => {
+                    self.cpu.request_interrupt(Interrupt::LCD);
+                }
+                GpuEvent::VBlank => {
+                    self.cpu.request_interrupt(Interrupt::VBlank);
+                    self.dots_since_frame = 0;
+                    self.record_frame(false);
+                }
             }
-            GpuEvent::VBlank => {
-                self.cpu.request_interrupt(Interrupt::VBlank);
-            }
-            _ => {}
+        }
+        // a switched off LCD shows nothing, but the recording mustn't lose the time
+        let dots_per_frame = DOTS_PER_LINE as u64 * LINES_PER_FRAME as u64;
+        if !self.cpu.mmu.vram().lcd_enabled() && self.dots_since_frame >= dots_per_frame {
+            self.dots_since_frame -= dots_per_frame;
+            self.record_frame(true);
+        }
+        for diagnostic in self.cpu.mmu.take_diagnostics() {
+            eprintln!("Warning: {}", diagnostic);
         }
 
         if let Some(new_ui_state) = ui_state {
             if UIState::has_negative_edge(&self.ui_state, &new_ui_state) {
                 self.cpu.request_interrupt(Interrupt::Joypad);
             }
+            let (x, y) = new_ui_state.tilt();
+            self.set_tilt(x, y);
+            self.set_infrared_light(new_ui_state.infrared_light());
             self.ui_state = new_ui_state;
         }
 
+        if let Some(battery_save) = &mut self.battery_save {
+            if let Err(error) = battery_save.update(self.cpu.mmu.mbc_mut()) {
+                eprintln!("Error writing {}: {}", battery_save.path().display(), error);
+            }
+        }
+
         ticks
     }
+
+    /// Runs without input for as long as `frames` frames take. Goes by the clock rather
+    /// than VBlanks so a game that keeps the LCD off still gets there.
+    pub fn run_frames(&mut self, frames: u64) {
+        let end = frames * DOTS_PER_LINE as u64 * LINES_PER_FRAME as u64;
+        let mut ticks = 0;
+        while ticks < end {
+            ticks += self.go(None);
+        }
+    }
+
+    /// Every frame from here on goes to `recorder`, until `stop_recording`.
+    pub fn start_recording(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn record_frame(&mut self, blank: bool) {
+        if self.recorder.is_none() {
+            return;
+        }
+        let frame = if blank {
+            self.blank_screen()
+        } else {
+            self.screenshot()
+        };
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(error) = recorder.add_frame(&frame) {
+                eprintln!("Error recording, stopped: {}", error);
+                self.recorder = None;
+            }
+        }
+    }
+
+    pub fn flush_battery_save(&mut self) {
+        if let Some(battery_save) = &mut self.battery_save {
+            if let Err(error) = battery_save.flush(self.cpu.mmu.mbc_mut()) {
+                eprintln!("Error writing {}: {}", battery_save.path().display(), error);
+            }
+        }
+    }
+
+    /// The frame on the LCD, SCREEN_WIDTH x SCREEN_HEIGHT shades. It changes when the
+    /// VBlank interrupt is requested, except for the first frame after the LCD comes on.
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.mmu.vram().screen()
+    }
+
+    pub fn set_colour_scheme(&mut self, colour_scheme: ColourScheme) {
+        self.colour_scheme = colour_scheme;
+    }
+
+    /// The framebuffer as packed RGB, three bytes a pixel, in the chosen colour scheme.
+    pub fn rgb_framebuffer(&self) -> Vec<u8> {
+        self.colour_scheme.resolve(self.framebuffer())
+    }
+
+    /// The screen as an image, for `scaled` and `save_png`. Needs no window to work.
+    pub fn screenshot(&self) -> Image {
+        Image::new(SCREEN_WIDTH, SCREEN_HEIGHT, self.rgb_framebuffer())
+    }
+
+    fn blank_screen(&self) -> Image {
+        let shades = [0u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+        Image::new(
+            SCREEN_WIDTH,
+            SCREEN_HEIGHT,
+            self.colour_scheme.resolve(&shades),
+        )
+    }
+
+    /// Writes the tile sheet, both background maps, the sprite previews and the OAM
+    /// table into `directory`, PNGs for looking at and JSON for scripts.
+    pub fn save_debug_views(&self, directory: &Path) -> io::Result<()> {
+        fs::create_dir_all(directory)?;
+        let vram = self.cpu.mmu.vram();
+        let scheme = &self.colour_scheme;
+        vram.tile_sheet(scheme)
+            .save_png(&directory.join("tiles.png"))?;
+        for map in [0x9800, 0x9C00] {
+            vram.background_map(map, scheme)
+                .save_png(&directory.join(format!("map_{:04X}.png", map)))?;
+        }
+        vram.oam_sheet(scheme)
+            .save_png(&directory.join("oam.png"))?;
+        write_atomic(
+            &directory.join("oam.json"),
+            oam_json(&vram.oam_table()).as_bytes(),
+        )?;
+
+        let maps: Vec<String> = [0x9800, 0x9C00]
+            .into_iter()
+            .map(|map| {
+                let numbers: Vec<String> = vram
+                    .map_tile_numbers(map)
+                    .iter()
+                    .map(|number| number.to_string())
+                    .collect();
+                format!("  \"{:04X}\": [{}]", map, numbers.join(", "))
+            })
+            .collect();
+        write_atomic(
+            &directory.join("maps.json"),
+            format!("{{\n{}\n}}\n", maps.join(",\n")).as_bytes(),
+        )
+    }
+
+    /// Polled by front-ends to forward the cartridge rumble motor to the host.
+    pub fn rumble(&self) -> bool {
+        self.cpu.mmu.mbc().rumble()
+    }
+
+    /// The tone the HuC3 speaker is playing, for front-ends to beep with.
+    pub fn speaker_tone(&self) -> Option<u8> {
+        self.cpu.mmu.mbc().speaker_tone()
+    }
+
+    /// Tilt in g, positive X to the right and positive Y towards the player. Keyboard,
+    /// mouse or scripted input all end up here; carts without an accelerometer ignore it.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cpu.mmu.mbc_mut().set_tilt(x, y);
+    }
+
+    pub fn infrared_led(&self) -> bool {
+        self.cpu.mmu.mbc().infrared_led()
+    }
+
+    pub fn set_infrared_light(&mut self, received: bool) {
+        self.cpu.mmu.mbc_mut().set_infrared_light(received);
+    }
+
+    pub fn set_camera_source(&mut self, source: CameraSource) {
+        self.cpu.mmu.mbc_mut().set_camera_source(source);
+    }
+}
+
+impl<'a, T: MBC> Drop for Gameboy<'a, T> {
+    // also runs while unwinding, so a crash still gets the last progress onto disk
+    fn drop(&mut self) {
+        self.flush_battery_save();
+        if let Err(error) = self.stop_recording() {
+            eprintln!("Error finishing recording: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cartridge, gpu::VRAM, mmu::MMU};
+
+    #[test]
+    fn test_recording_continues_with_lcd_off() {
+        let rom = vec![0u8; 0x8000];
+        let gpu = Box::leak(Box::new(VRAM::new()));
+        let mmu = Box::leak(Box::new(MMU::new(gpu, cartridge::from_rom(&rom).unwrap())));
+        let mut cpu = CPU::new(mmu);
+        cpu.skip_boot();
+        cpu.mmu.write(0xFF40, 0x00);
+        let mut gameboy = Gameboy::new(cpu, None);
+
+        let path = std::env::temp_dir().join(format!("gb_lcd_off_{}.y4m", std::process::id()));
+        gameboy.start_recording(Recorder::from_path(&path).unwrap());
+        gameboy.run_frames(10);
+        gameboy.stop_recording().unwrap();
+
+        let y4m = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let frames = y4m.windows(6).filter(|window| window == b"FRAME\n").count();
+        assert_eq!(frames, 10);
+    }
 }
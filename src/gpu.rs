@@ -1,9 +1,35 @@
+mod debug;
+mod fifo;
+mod scanline;
+
+use crate::model::Model;
+use crate::sprite::Sprite;
+pub use debug::oam_json;
+use fifo::PixelFifo;
+use std::fmt;
+
 pub enum GpuEvent {
-    None,
     LCD,
     VBlank,
 }
 
+/// Things a game did that happen to work here but misbehave on real hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Diagnostic {
+    // the original DMG screen can be damaged by this
+    LcdOffOutsideVBlank { ly: u8 },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::LcdOffOutsideVBlank { ly } => {
+                write!(f, "LCD turned off outside VBlank, on line {}", ly)
+            }
+        }
+    }
+}
+
 pub struct LCDC {
     lcd_enabled: bool,            // bit 7
     window_tile_map_select: bool, // bit 6
@@ -28,7 +54,20 @@ impl LCDC {
     }
 
     pub fn get(&self) -> u8 {
-        0u8
+        let flags = [
+            (self.lcd_enabled, 0x80),
+            (self.window_tile_map_select, 0x40),
+            (self.window_display_toggle, 0x20),
+            (self.bg_window_tile_select, 0x10),
+            (self.bg_tile_map_select, 0x08),
+            (self.sprite_size, 0x04),
+            (self.sprite_display, 0x02),
+            (self.bg_display, 0x01),
+        ];
+        flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .fold(0u8, |val, (_, bit)| val | bit)
     }
 
     pub fn new() -> LCDC {
@@ -46,29 +85,417 @@ impl LCDC {
 }
 
 pub struct STAT {
+    lyc_interrupt: bool,    // bit 6
+    oam_interrupt: bool,    // bit 5
+    vblank_interrupt: bool, // bit 4
+    hblank_interrupt: bool, // bit 3
     lyc_ly_coincidence: bool,
 }
 
+impl STAT {
+    // only the interrupt selects are writable, the rest is the PPU's own state
+    pub fn set(&mut self, val: u8) {
+        self.lyc_interrupt = (val & 0x40) > 0;
+        self.oam_interrupt = (val & 0x20) > 0;
+        self.vblank_interrupt = (val & 0x10) > 0;
+        self.hblank_interrupt = (val & 0x08) > 0;
+    }
+
+    pub fn get(&self, mode: Mode) -> u8 {
+        let mut val = 0x80 | mode as u8;
+        if self.lyc_interrupt {
+            val |= 0x40;
+        }
+        if self.oam_interrupt {
+            val |= 0x20;
+        }
+        if self.vblank_interrupt {
+            val |= 0x10;
+        }
+        if self.hblank_interrupt {
+            val |= 0x08;
+        }
+        if self.lyc_ly_coincidence {
+            val |= 0x04;
+        }
+        val
+    }
+
+    pub fn new() -> STAT {
+        STAT {
+            lyc_interrupt: false,
+            oam_interrupt: false,
+            vblank_interrupt: false,
+            hblank_interrupt: false,
+            lyc_ly_coincidence: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// How mode 3 gets drawn. The scanline renderer draws a whole line at once and is
+/// cheap, the pixel FIFO follows the hardware a dot at a time for mid-line effects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Renderer {
+    Scanline,
+    Fifo,
+}
+
+impl Renderer {
+    pub fn from_name(name: &str) -> Option<Renderer> {
+        match name {
+            "scanline" => Some(Renderer::Scanline),
+            "fifo" => Some(Renderer::Fifo),
+            _ => None,
+        }
+    }
+}
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+pub const DOTS_PER_LINE: u16 = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+
 pub struct VRAM {
     memory: Box<[u8; 0x2000]>,
-    oam: Box<[u8; 0xA0]>,
+    oam: Box<[Sprite; 40]>,
     lcdc: LCDC,
-    ticks: u64,
+    stat: STAT,
+    mode: Mode,
+    // the line being drawn, which LY doesn't always show
+    line: u8,
+    dot: u16,
+    ly: u8,
+    lyc: u8,
+    scy: u8,
+    scx: u8,
+    wy: u8,
+    wx: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    // the window keeps its own line count, it only advances on lines it was drawn on
+    window_line: u8,
+    window_triggered: bool,
+    // the OR of every enabled STAT source, interrupts fire on its rising edge only
+    stat_line: bool,
+    framebuffer: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    // what the LCD shows, the framebuffer is copied over at VBlank
+    screen: Box<[u8; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+    // the first frame after turning the LCD on never makes it to the screen
+    skip_frame: bool,
+    diagnostics: Vec<Diagnostic>,
+    renderer: Renderer,
+    fifo: PixelFifo,
+    model: Model,
 }
 
 impl VRAM {
     pub fn new() -> VRAM {
         VRAM {
             memory: Box::new([0u8; 0x2000]),
-            oam: Box::new([0u8; 0xA0]),
-            ticks: 0,
+            oam: Box::new([(); 40].map(|_| Sprite::new())),
             lcdc: LCDC::new(),
+            stat: STAT::new(),
+            mode: Mode::HBlank,
+            line: 0,
+            dot: 0,
+            ly: 0,
+            lyc: 0,
+            scy: 0,
+            scx: 0,
+            wy: 0,
+            wx: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            window_line: 0,
+            window_triggered: false,
+            stat_line: false,
+            framebuffer: Box::new([0u8; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            screen: Box::new([0u8; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            skip_frame: false,
+            diagnostics: Vec::new(),
+            renderer: Renderer::Scanline,
+            fifo: PixelFifo::new(),
+            model: Model::DMG,
         }
     }
 
-    pub fn go(&mut self, ticks: u64) -> GpuEvent {
-        self.ticks = self.ticks + ticks;
-        GpuEvent::None
+    /// Where the boot ROM hands over, a few dots before LY wraps round to line 0.
+    pub fn skip_boot(&mut self) {
+        self.line = 153;
+        self.dot = 400;
+        self.update_ly();
+        self.mode = Mode::VBlank;
+        self.skip_frame = false;
+        self.update_stat(&mut Vec::new());
+    }
+
+    pub fn go(&mut self, ticks: u64) -> Vec<GpuEvent> {
+        let mut events = Vec::new();
+        if !self.lcdc.lcd_enabled {
+            return events;
+        }
+
+        for _ in 0..ticks {
+            self.step(&mut events);
+        }
+        events
+    }
+
+    fn step(&mut self, events: &mut Vec<GpuEvent>) {
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.line = (self.line + 1) % LINES_PER_FRAME;
+            match self.line {
+                0 => {
+                    self.window_line = 0;
+                    self.window_triggered = false;
+                }
+                144 => {
+                    if self.skip_frame {
+                        self.skip_frame = false;
+                    } else {
+                        self.screen.copy_from_slice(&self.framebuffer[..]);
+                    }
+                    events.push(GpuEvent::VBlank);
+                }
+                _ => {}
+            }
+        }
+
+        let mode = match (self.line, self.dot) {
+            (144.., _) => Mode::VBlank,
+            (_, dot) if dot < OAM_SCAN_DOTS => Mode::OamScan,
+            _ if self.mode == Mode::HBlank => Mode::HBlank,
+            (_, dot) => match self.renderer {
+                Renderer::Scanline if dot < OAM_SCAN_DOTS + DRAWING_DOTS => Mode::Drawing,
+                Renderer::Fifo if self.mode == Mode::OamScan || !self.fifo.done() => Mode::Drawing,
+                _ => Mode::HBlank,
+            },
+        };
+        if mode == Mode::OamScan && self.line == self.wy {
+            self.window_triggered = true;
+        }
+        match self.renderer {
+            Renderer::Scanline => {
+                if self.mode == Mode::Drawing && mode == Mode::HBlank {
+                    self.render_scanline();
+                }
+            }
+            Renderer::Fifo => {
+                if self.mode == Mode::OamScan && mode == Mode::Drawing {
+                    self.fifo_start_line();
+                }
+                if mode == Mode::Drawing {
+                    self.fifo_dot();
+                }
+            }
+        }
+        self.mode = mode;
+        self.update_ly();
+        self.update_stat(events);
+    }
+
+    // LY already reads 0 a few dots into line 153
+    fn update_ly(&mut self) {
+        self.ly = if self.line == 153 && self.dot >= 4 {
+            0
+        } else {
+            self.line
+        };
+    }
+
+    fn update_stat(&mut self, events: &mut Vec<GpuEvent>) {
+        self.stat.lyc_ly_coincidence = self.ly == self.lyc;
+
+        let stat_line = (self.stat.lyc_interrupt && self.stat.lyc_ly_coincidence)
+            || (self.stat.hblank_interrupt && self.mode == Mode::HBlank)
+            || (self.stat.vblank_interrupt && self.mode == Mode::VBlank)
+            || (self.stat.oam_interrupt && self.mode == Mode::OamScan);
+        if stat_line && !self.stat_line {
+            events.push(GpuEvent::LCD);
+        }
+        self.stat_line = stat_line;
+    }
+
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    /// The DMG family's OAM bug: a 16-bit increment or decrement of a pointer into
+    /// 0xFE00..=0xFEFF while OAM is being scanned mangles the row the PPU is reading.
+    pub fn corrupt_oam(&mut self) {
+        if self.model.is_cgb() || !self.lcdc.lcd_enabled || self.mode != Mode::OamScan {
+            return;
+        }
+        // one 8 byte row every 4 dots, the first row is never hit
+        let row = (self.dot / 4) as usize;
+        if row == 0 || row >= 20 {
+            return;
+        }
+
+        let current = row * 8;
+        let previous = current - 8;
+        let word = |vram: &VRAM, address: usize| {
+            u16::from_le_bytes([vram.read_oam(address), vram.read_oam(address + 1)])
+        };
+        let a = word(self, current);
+        let b = word(self, previous);
+        let c = word(self, previous + 4);
+        let [low, high] = (((a ^ c) & (b ^ c)) ^ c).to_le_bytes();
+        self.write_oam(current, low);
+        self.write_oam(current + 1, high);
+        for offset in 2..8 {
+            let value = self.read_oam(previous + offset);
+            self.write_oam(current + offset, value);
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc.lcd_enabled
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // the PPU has VRAM to itself while drawing and OAM from the start of the OAM scan
+    pub fn vram_accessible(&self) -> bool {
+        self.mode != Mode::Drawing
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        !matches!(self.mode, Mode::OamScan | Mode::Drawing)
+    }
+
+    /// The last frame drawn, a shade 0-3 per pixel (0 lightest) in rows of SCREEN_WIDTH.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer[..]
+    }
+
+    /// The frame on the LCD, laid out like the framebuffer. Blank while the LCD is off.
+    pub fn screen(&self) -> &[u8] {
+        &self.screen[..]
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    fn set_lcdc(&mut self, value: u8, events: &mut Vec<GpuEvent>) {
+        let was_enabled = self.lcdc.lcd_enabled;
+        self.lcdc.set(value);
+        match (was_enabled, self.lcdc.lcd_enabled) {
+            (true, false) => {
+                if self.mode != Mode::VBlank {
+                    self.diagnostics
+                        .push(Diagnostic::LcdOffOutsideVBlank { ly: self.ly });
+                }
+                self.line = 0;
+                self.dot = 0;
+                self.ly = 0;
+                self.mode = Mode::HBlank;
+                self.stat_line = false;
+                self.screen.fill(0);
+            }
+            (false, true) => {
+                self.mode = Mode::OamScan;
+                self.skip_frame = true;
+                self.update_stat(events);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn read_register(&self, address: usize) -> u8 {
+        match address {
+            0xFF40 => self.lcdc.get(),
+            0xFF41 => self.stat.get(self.mode),
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => panic!("invalid PPU register, this must be a programming error"),
+        }
+    }
+
+    /// Register writes can raise the STAT line too, those events are returned.
+    pub fn write_register(&mut self, address: usize, value: u8) -> Vec<GpuEvent> {
+        let mut events = Vec::new();
+        match address {
+            0xFF40 => self.set_lcdc(value, &mut events),
+            0xFF41 => {
+                // the DMG family acts as if 0xFF was written for a cycle first, so any
+                // source that's already true fires
+                if !self.model.is_cgb() {
+                    self.stat.set(0xFF);
+                    self.update_stat(&mut events);
+                }
+                self.stat.set(value);
+                self.update_stat(&mut events);
+            }
+            0xFF44 => {} // read only
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF45 => {
+                self.lyc = value;
+                self.update_stat(&mut events);
+            }
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            _ => panic!("invalid PPU register, this must be a programming error"),
+        }
+        events
+    }
+
+    /// Sets a register's backing value without any of the side effects of a bus write.
+    pub fn load_register(&mut self, address: usize, value: u8) {
+        match address {
+            0xFF40 => self.lcdc.set(value),
+            0xFF41 => self.stat.set(value),
+            0xFF44 => {} // read only
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF45 => self.lyc = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            _ => panic!("invalid PPU register, this must be a programming error"),
+        }
+    }
+
+    pub fn read_oam(&self, address: usize) -> u8 {
+        self.oam[address / 4].read_sprite_address(address)
+    }
+
+    pub fn write_oam(&mut self, address: usize, value: u8) {
+        self.oam[address / 4].write_sprite_address(address, value);
     }
 
     pub fn read(&self, address: usize) -> u8 {
@@ -85,3 +512,208 @@ impl VRAM {
         &mut self.memory[index]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lcd_on() -> VRAM {
+        let mut vram = VRAM::new();
+        vram.write_register(0xFF40, 0x80);
+        vram
+    }
+
+    fn count(events: &[GpuEvent], lcd: bool) -> usize {
+        events
+            .iter()
+            .filter(|event| matches!(event, GpuEvent::LCD) == lcd)
+            .count()
+    }
+
+    #[test]
+    fn test_mode_timing() {
+        let mut vram = lcd_on();
+        assert_eq!(vram.mode(), Mode::OamScan);
+        vram.go(79);
+        assert_eq!(vram.mode(), Mode::OamScan);
+        vram.go(1);
+        assert_eq!(vram.mode(), Mode::Drawing);
+        vram.go(172);
+        assert_eq!(vram.mode(), Mode::HBlank);
+        vram.go(204);
+        assert_eq!(vram.mode(), Mode::OamScan);
+        assert_eq!(vram.read_register(0xFF44), 1);
+    }
+
+    #[test]
+    fn test_vblank_once_per_frame() {
+        let mut vram = lcd_on();
+        let events = vram.go(456 * 144);
+        assert_eq!(count(&events, false), 1);
+        assert_eq!(vram.mode(), Mode::VBlank);
+        assert_eq!(vram.read_register(0xFF44), 144);
+        assert_eq!(vram.read_register(0xFF41) & 0x03, 1);
+
+        let events = vram.go(456 * 154);
+        assert_eq!(count(&events, false), 1);
+    }
+
+    #[test]
+    fn test_ly_wraps_early_on_line_153() {
+        let mut vram = lcd_on();
+        vram.go(456 * 153 + 3);
+        assert_eq!(vram.read_register(0xFF44), 153);
+        vram.go(1);
+        assert_eq!(vram.read_register(0xFF44), 0);
+        vram.go(452);
+        assert_eq!(vram.read_register(0xFF44), 0);
+        assert_eq!(vram.mode(), Mode::OamScan);
+    }
+
+    #[test]
+    fn test_lyc_coincidence_interrupt() {
+        let mut vram = lcd_on();
+        vram.write_register(0xFF45, 2);
+        vram.write_register(0xFF41, 0x40);
+        assert_eq!(vram.read_register(0xFF41) & 0x04, 0);
+
+        let events = vram.go(456 * 2);
+        assert_eq!(count(&events, true), 1);
+        assert_eq!(vram.read_register(0xFF41) & 0x04, 0x04);
+        assert_eq!(vram.read_register(0xFF41), 0xC6);
+    }
+
+    #[test]
+    fn test_stat_blocking() {
+        let mut vram = lcd_on();
+        // HBlank of line 1 runs straight into the LYC match on line 2, the line never drops
+        vram.write_register(0xFF45, 2);
+        vram.write_register(0xFF41, 0x48);
+        vram.go(456);
+        let events = vram.go(456);
+        assert_eq!(count(&events, true), 1);
+
+        // without LYC, HBlank on both lines
+        let mut vram = lcd_on();
+        vram.write_register(0xFF41, 0x08);
+        let events = vram.go(456 * 2);
+        assert_eq!(count(&events, true), 2);
+    }
+
+    #[test]
+    fn test_writing_lyc_can_interrupt() {
+        let mut vram = lcd_on();
+        vram.write_register(0xFF41, 0x40);
+        vram.write_register(0xFF45, 5);
+        let events = vram.write_register(0xFF45, 0);
+        assert_eq!(count(&events, true), 1);
+    }
+
+    #[test]
+    fn test_stat_write_bug() {
+        let mut vram = lcd_on();
+        vram.go(100);
+        assert_eq!(count(&vram.write_register(0xFF41, 0x00), true), 1);
+
+        let mut vram = lcd_on();
+        vram.set_model(Model::CGB);
+        vram.go(100);
+        assert!(vram.write_register(0xFF41, 0x00).is_empty());
+    }
+
+    #[test]
+    fn test_oam_bug() {
+        let mut vram = lcd_on();
+        for address in 0..0xA0 {
+            vram.write_oam(address, address as u8);
+        }
+        // into line 1, far enough for row 2
+        vram.go(456 + 8);
+        vram.corrupt_oam();
+        // (a ^ c) & (b ^ c) ^ c with a = 0x1110, b = 0x0908, c = 0x0D0C
+        assert_eq!(vram.read_oam(0x10), 0x08);
+        assert_eq!(vram.read_oam(0x11), 0x09);
+        assert_eq!(vram.read_oam(0x12), 0x0A);
+        assert_eq!(vram.read_oam(0x17), vram.read_oam(0x0F));
+
+        let mut vram = lcd_on();
+        vram.set_model(Model::CGB);
+        vram.write_oam(0x10, 0x55);
+        vram.go(456 + 8);
+        vram.corrupt_oam();
+        assert_eq!(vram.read_oam(0x10), 0x55);
+    }
+
+    #[test]
+    fn test_lcd_off_stops_ppu() {
+        let mut vram = VRAM::new();
+        assert!(vram.go(456 * 154).is_empty());
+        assert_eq!(vram.read_register(0xFF44), 0);
+    }
+
+    #[test]
+    fn test_access_by_mode() {
+        let mut vram = VRAM::new();
+        assert!(vram.vram_accessible() && vram.oam_accessible());
+
+        vram.write_register(0xFF40, 0x80);
+        assert!(vram.vram_accessible() && !vram.oam_accessible());
+        vram.go(80);
+        assert!(!vram.vram_accessible() && !vram.oam_accessible());
+        vram.go(172);
+        assert!(vram.vram_accessible() && vram.oam_accessible());
+        vram.go(456 * 144);
+        assert_eq!(vram.mode(), Mode::VBlank);
+        assert!(vram.vram_accessible() && vram.oam_accessible());
+    }
+
+    #[test]
+    fn test_lcd_off_resets_ly_and_mode() {
+        let mut vram = lcd_on();
+        vram.go(456 * 150);
+        vram.write_register(0xFF40, 0x00);
+        assert_eq!(vram.read_register(0xFF44), 0);
+        assert_eq!(vram.read_register(0xFF41) & 0x03, 0);
+        assert!(vram.take_diagnostics().is_empty());
+
+        vram.go(456 * 10);
+        assert_eq!(vram.read_register(0xFF44), 0);
+
+        vram.write_register(0xFF40, 0x80);
+        assert_eq!(vram.mode(), Mode::OamScan);
+        vram.go(456 * 3);
+        assert_eq!(vram.read_register(0xFF44), 3);
+    }
+
+    #[test]
+    fn test_lcd_off_outside_vblank_diagnostic() {
+        let mut vram = lcd_on();
+        vram.go(456 * 20 + 100);
+        vram.write_register(0xFF40, 0x00);
+        assert_eq!(
+            vram.take_diagnostics(),
+            vec![Diagnostic::LcdOffOutsideVBlank { ly: 20 }]
+        );
+        assert!(vram.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_first_frame_after_lcd_on_is_skipped() {
+        let mut vram = VRAM::new();
+        for i in 0..16 {
+            vram.write(0x8000 + i, 0xFF);
+        }
+        vram.write_register(0xFF47, 0xE4);
+        vram.write_register(0xFF40, 0x91);
+
+        vram.go(456 * 154);
+        assert!(vram.framebuffer().iter().all(|shade| *shade == 3));
+        assert!(vram.screen().iter().all(|shade| *shade == 0));
+
+        vram.go(456 * 154);
+        assert!(vram.screen().iter().all(|shade| *shade == 3));
+
+        vram.write_register(0xFF40, 0x11);
+        assert!(vram.screen().iter().all(|shade| *shade == 0));
+    }
+}
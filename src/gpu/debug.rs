@@ -0,0 +1,248 @@
+use super::scanline::shade;
+use super::VRAM;
+use crate::colour_scheme::ColourScheme;
+use crate::image::Image;
+
+const TILE_COUNT: usize = 384;
+const SHEET_COLUMNS: usize = 16;
+const OAM_COLUMNS: usize = 10;
+const VIEWPORT_COLOUR: [u8; 3] = [0xFF, 0x00, 0x00];
+
+/// One OAM entry as the sprite table shows it. X and Y are the raw values, so the
+/// sprite's top left on screen is at X-8, Y-16.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpriteEntry {
+    pub index: usize,
+    pub x: u8,
+    pub y: u8,
+    pub tile_number: u8,
+    pub palette: u8,
+    pub x_flip: bool,
+    pub y_flip: bool,
+    pub behind_background: bool,
+}
+
+impl SpriteEntry {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"index\": {}, \"x\": {}, \"y\": {}, \"tile_number\": {}, \"palette\": {}, \
+             \"x_flip\": {}, \"y_flip\": {}, \"behind_background\": {}}}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile_number,
+            self.palette,
+            self.x_flip,
+            self.y_flip,
+            self.behind_background
+        )
+    }
+}
+
+pub fn oam_json(entries: &[SpriteEntry]) -> String {
+    let entries: Vec<String> = entries
+        .iter()
+        .map(|entry| format!("  {}", entry.to_json()))
+        .collect();
+    format!("[\n{}\n]\n", entries.join(",\n"))
+}
+
+impl VRAM {
+    /// All 384 tiles from 0x8000 in rows of 16, through BGP.
+    pub fn tile_sheet(&self, scheme: &ColourScheme) -> Image {
+        let width = SHEET_COLUMNS * 8;
+        let height = TILE_COUNT / SHEET_COLUMNS * 8;
+        let mut image = Image::new(width, height, vec![0; width * height * 3]);
+        for tile in 0..TILE_COUNT {
+            let left = tile % SHEET_COLUMNS * 8;
+            let top = tile / SHEET_COLUMNS * 8;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let colour = self.tile_pixel(0x8000 + tile * 16, x, y);
+                    let rgb = scheme.colour(shade(self.bgp, colour));
+                    image.set_pixel(left + x as usize, top + y as usize, rgb);
+                }
+            }
+        }
+        image
+    }
+
+    /// The whole 256x256 map at 0x9800 or 0x9C00, with the area SCX/SCY show outlined.
+    pub fn background_map(&self, map: usize, scheme: &ColourScheme) -> Image {
+        let mut image = Image::new(256, 256, vec![0; 256 * 256 * 3]);
+        for y in 0..256 {
+            for x in 0..256 {
+                let colour = self.map_pixel(map, x as u8, y as u8);
+                image.set_pixel(x, y, scheme.colour(shade(self.bgp, colour)));
+            }
+        }
+
+        // the viewport wraps round the edges of the map just like the picture does
+        let (left, top) = (self.scx as usize, self.scy as usize);
+        for x in 0..160 {
+            image.set_pixel((left + x) % 256, top, VIEWPORT_COLOUR);
+            image.set_pixel((left + x) % 256, (top + 143) % 256, VIEWPORT_COLOUR);
+        }
+        for y in 0..144 {
+            image.set_pixel(left, (top + y) % 256, VIEWPORT_COLOUR);
+            image.set_pixel((left + 159) % 256, (top + y) % 256, VIEWPORT_COLOUR);
+        }
+        image
+    }
+
+    /// The 32x32 tile numbers of a map, row by row.
+    pub fn map_tile_numbers(&self, map: usize) -> Vec<u8> {
+        (0..32 * 32).map(|index| self.read(map + index)).collect()
+    }
+
+    pub fn oam_table(&self) -> Vec<SpriteEntry> {
+        self.oam
+            .iter()
+            .enumerate()
+            .map(|(index, sprite)| SpriteEntry {
+                index,
+                x: sprite.x,
+                y: sprite.y,
+                tile_number: sprite.tile_number,
+                palette: sprite.pallete_number,
+                x_flip: sprite.x_flip_flag,
+                y_flip: sprite.y_flip_flag,
+                behind_background: sprite.priority_flag,
+            })
+            .collect()
+    }
+
+    /// A sprite drawn the way it would be on screen, flips and palette included.
+    pub fn sprite_preview(&self, index: usize, scheme: &ColourScheme) -> Image {
+        let sprite = &self.oam[index];
+        let height = self.sprite_height() as usize;
+        let palette = if sprite.pallete_number == 0 {
+            self.obp0
+        } else {
+            self.obp1
+        };
+        let mut tile_number = sprite.tile_number as usize;
+        if height == 16 {
+            tile_number &= 0xFE;
+        }
+
+        let mut image = Image::new(8, height, vec![0; 8 * height * 3]);
+        for y in 0..height {
+            for x in 0..8 {
+                let row = if sprite.y_flip_flag {
+                    height - 1 - y
+                } else {
+                    y
+                };
+                let column = if sprite.x_flip_flag { 7 - x } else { x };
+                let colour = self.tile_pixel(0x8000 + tile_number * 16, column as u8, row as u8);
+                image.set_pixel(x, y, scheme.colour(shade(palette, colour)));
+            }
+        }
+        image
+    }
+
+    /// Every sprite's preview in OAM order, 10 to a row in 8x16 cells.
+    pub fn oam_sheet(&self, scheme: &ColourScheme) -> Image {
+        let width = OAM_COLUMNS * 8;
+        let height = self.oam.len() / OAM_COLUMNS * 16;
+        let mut image = Image::new(width, height, vec![0; width * height * 3]);
+        for index in 0..self.oam.len() {
+            let preview = self.sprite_preview(index, scheme);
+            let left = index % OAM_COLUMNS * 8;
+            let top = index / OAM_COLUMNS * 16;
+            for y in 0..preview.height() {
+                for x in 0..preview.width() {
+                    image.set_pixel(left + x, top + y, preview.pixel(x, y));
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEME: ColourScheme = ColourScheme::DMG;
+
+    fn vram() -> VRAM {
+        let mut vram = VRAM::new();
+        vram.write_register(0xFF47, 0xE4);
+        vram.write_register(0xFF48, 0xE4);
+        vram.write_register(0xFF40, 0x11);
+        vram
+    }
+
+    #[test]
+    fn test_tile_sheet() {
+        let mut vram = vram();
+        // the last tile, top left pixel colour 3
+        vram.write(0x8000 + 383 * 16, 0x80);
+        vram.write(0x8000 + 383 * 16 + 1, 0x80);
+
+        let sheet = vram.tile_sheet(&SCHEME);
+        assert_eq!((sheet.width(), sheet.height()), (128, 192));
+        assert_eq!(sheet.pixel(120, 184), SCHEME.colour(3));
+        assert_eq!(sheet.pixel(121, 184), SCHEME.colour(0));
+    }
+
+    #[test]
+    fn test_background_map_viewport() {
+        let mut vram = vram();
+        vram.write(0x8010, 0xFF);
+        vram.write(0x9C00 + 33, 1);
+        vram.write_register(0xFF43, 200);
+        vram.write_register(0xFF42, 10);
+
+        let map = vram.background_map(0x9C00, &SCHEME);
+        assert_eq!(map.pixel(8, 8), SCHEME.colour(1));
+        assert_eq!(map.pixel(8, 9), SCHEME.colour(0));
+        // the outline wraps past the right edge
+        assert_eq!(map.pixel(200, 50), VIEWPORT_COLOUR);
+        assert_eq!(map.pixel((200 + 159) % 256, 50), VIEWPORT_COLOUR);
+        assert_eq!(map.pixel(0, 10), VIEWPORT_COLOUR);
+        assert_eq!(map.pixel(50, 50), SCHEME.colour(0));
+
+        assert_eq!(vram.map_tile_numbers(0x9C00)[33], 1);
+    }
+
+    #[test]
+    fn test_oam_table_and_preview() {
+        let mut vram = vram();
+        // tile 2, a single colour 1 pixel in its top left corner
+        vram.write(0x8020, 0x80);
+        for (offset, value) in [40, 30, 2, 0x20].into_iter().enumerate() {
+            vram.write_oam(4 + offset, value);
+        }
+
+        let table = vram.oam_table();
+        assert_eq!(table.len(), 40);
+        assert_eq!(
+            table[1],
+            SpriteEntry {
+                index: 1,
+                x: 30,
+                y: 40,
+                tile_number: 2,
+                palette: 0,
+                x_flip: true,
+                y_flip: false,
+                behind_background: false,
+            }
+        );
+        assert!(oam_json(&table).contains(
+            "{\"index\": 1, \"x\": 30, \"y\": 40, \"tile_number\": 2, \"palette\": 0, \
+             \"x_flip\": true, \"y_flip\": false, \"behind_background\": false}"
+        ));
+
+        let preview = vram.sprite_preview(1, &SCHEME);
+        assert_eq!(preview.pixel(7, 0), SCHEME.colour(1));
+        assert_eq!(preview.pixel(0, 0), SCHEME.colour(0));
+
+        let sheet = vram.oam_sheet(&SCHEME);
+        assert_eq!((sheet.width(), sheet.height()), (80, 64));
+        assert_eq!(sheet.pixel(15, 0), SCHEME.colour(1));
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::VecDeque;
+
+use super::scanline::shade;
+use super::{SCREEN_WIDTH, VRAM};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fetcher {
+    step: FetchStep,
+    // dots spent on the current step, every step but Push takes two
+    dots: u8,
+    tile_x: u8,
+    window: bool,
+    tile_number: u8,
+    low: u8,
+    high: u8,
+}
+
+impl Fetcher {
+    fn new(window: bool) -> Fetcher {
+        Fetcher {
+            step: FetchStep::Tile,
+            dots: 0,
+            tile_x: 0,
+            window,
+            tile_number: 0,
+            low: 0,
+            high: 0,
+        }
+    }
+
+    // how long until the background fetcher would get to push again
+    fn remaining(&self) -> u8 {
+        match self.step {
+            FetchStep::Tile => 6 - self.dots,
+            FetchStep::DataLow => 4 - self.dots,
+            FetchStep::DataHigh => 2 - self.dots,
+            FetchStep::Push => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SpritePixel {
+    colour: u8,
+    palette: u8,
+    priority: bool,
+}
+
+const TRANSPARENT: SpritePixel = SpritePixel {
+    colour: 0,
+    palette: 0,
+    priority: false,
+};
+
+/// Background and sprite FIFOs fed by the fetchers a dot at a time, so mode 3 takes as
+/// long as it does on hardware and register writes land on the pixel they hit.
+pub(super) struct PixelFifo {
+    background: VecDeque<u8>,
+    sprites: VecDeque<SpritePixel>,
+    fetcher: Fetcher,
+    x: usize,
+    // SCX fine scroll, these pixels get shifted out without being drawn
+    discard: u8,
+    line_sprites: Vec<usize>,
+    sprite_stall: u8,
+    pending_sprite: Option<usize>,
+    window_active: bool,
+}
+
+impl PixelFifo {
+    pub(super) fn new() -> PixelFifo {
+        PixelFifo {
+            background: VecDeque::with_capacity(16),
+            sprites: VecDeque::with_capacity(8),
+            fetcher: Fetcher::new(false),
+            x: 0,
+            discard: 0,
+            line_sprites: Vec::new(),
+            sprite_stall: 0,
+            pending_sprite: None,
+            window_active: false,
+        }
+    }
+
+    pub(super) fn done(&self) -> bool {
+        self.x == SCREEN_WIDTH
+    }
+}
+
+impl VRAM {
+    pub(super) fn fifo_start_line(&mut self) {
+        let line_sprites = self.line_sprites();
+        let fifo = &mut self.fifo;
+        fifo.background.clear();
+        fifo.sprites.clear();
+        fifo.fetcher = Fetcher::new(false);
+        fifo.x = 0;
+        fifo.discard = self.scx & 0x07;
+        fifo.line_sprites = line_sprites;
+        // the first fetch of a line is thrown away
+        fifo.sprite_stall = 6;
+        fifo.pending_sprite = None;
+        fifo.window_active = false;
+    }
+
+    /// One dot of mode 3, producing at most one pixel.
+    pub(super) fn fifo_dot(&mut self) {
+        if self.fifo.sprite_stall > 0 {
+            self.fifo.sprite_stall -= 1;
+            if self.fifo.sprite_stall == 0 {
+                if let Some(index) = self.fifo.pending_sprite.take() {
+                    self.fifo_load_sprite(index);
+                }
+            }
+            return;
+        }
+
+        self.fifo_check_window();
+        self.fifo_fetch();
+
+        if self.fifo.background.is_empty() {
+            return;
+        }
+        if self.fifo.discard == 0 && self.fifo_check_sprites() {
+            return;
+        }
+        self.fifo_shift_out();
+    }
+
+    fn fifo_check_window(&mut self) {
+        let wx = self.wx as usize;
+        if self.fifo.window_active
+            || !self.lcdc.window_display_toggle
+            || !self.window_triggered
+            || wx > 166
+            || self.fifo.x + 7 < wx
+        {
+            return;
+        }
+
+        self.fifo.window_active = true;
+        self.fifo.background.clear();
+        self.fifo.fetcher = Fetcher::new(true);
+        // WX below 7 pushes the start of the window off the left edge
+        if self.fifo.x == 0 && wx < 7 {
+            self.fifo.discard = 7 - wx as u8;
+        }
+    }
+
+    fn fifo_fetch(&mut self) {
+        let mut fetcher = self.fifo.fetcher;
+        if fetcher.step == FetchStep::Push {
+            if self.fifo.background.is_empty() {
+                for bit in (0..8).rev() {
+                    let colour = if self.lcdc.bg_display {
+                        (((fetcher.high >> bit) & 0x01) << 1) | ((fetcher.low >> bit) & 0x01)
+                    } else {
+                        0
+                    };
+                    self.fifo.background.push_back(colour);
+                }
+                fetcher.tile_x = fetcher.tile_x.wrapping_add(1);
+                fetcher.step = FetchStep::Tile;
+            }
+            self.fifo.fetcher = fetcher;
+            return;
+        }
+
+        fetcher.dots += 1;
+        if fetcher.dots == 2 {
+            fetcher.dots = 0;
+            let (tile_row, map_address) = if fetcher.window {
+                let map = if self.lcdc.window_tile_map_select {
+                    0x9C00
+                } else {
+                    0x9800
+                };
+                let y = self.window_line as usize;
+                (y % 8, map + (y / 8) * 32 + (fetcher.tile_x as usize & 31))
+            } else {
+                let map = if self.lcdc.bg_tile_map_select {
+                    0x9C00
+                } else {
+                    0x9800
+                };
+                let y = self.line.wrapping_add(self.scy) as usize;
+                let x = (self.scx as usize / 8 + fetcher.tile_x as usize) & 31;
+                (y % 8, map + (y / 8) * 32 + x)
+            };
+            let data_address = self.tile_address(fetcher.tile_number) + tile_row * 2;
+
+            fetcher.step = match fetcher.step {
+                FetchStep::Tile => {
+                    fetcher.tile_number = self.read(map_address);
+                    FetchStep::DataLow
+                }
+                FetchStep::DataLow => {
+                    fetcher.low = self.read(data_address);
+                    FetchStep::DataHigh
+                }
+                _ => {
+                    fetcher.high = self.read(data_address + 1);
+                    FetchStep::Push
+                }
+            };
+        }
+        self.fifo.fetcher = fetcher;
+    }
+
+    // starts fetching the next sprite that begins at the current pixel, if there is one.
+    // Sprites further left win, then the lower OAM index, like the DMG's priority
+    fn fifo_check_sprites(&mut self) -> bool {
+        if !self.lcdc.sprite_display {
+            return false;
+        }
+        let x = self.fifo.x as i16;
+        let position = self
+            .fifo
+            .line_sprites
+            .iter()
+            .enumerate()
+            .filter(|(_, &index)| self.oam[index].x as i16 - 8 <= x)
+            .min_by_key(|(_, &index)| (self.oam[index].x, index))
+            .map(|(position, _)| position);
+        let Some(position) = position else {
+            return false;
+        };
+
+        let index = self.fifo.line_sprites.remove(position);
+        self.fifo.pending_sprite = Some(index);
+        // the background fetch in flight finishes first, then six dots for the sprite
+        self.fifo.sprite_stall = 6 + self.fifo.fetcher.remaining();
+        true
+    }
+
+    fn fifo_load_sprite(&mut self, index: usize) {
+        let left = self.oam[index].x as i16 - 8;
+        let palette = self.oam[index].pallete_number;
+        let priority = self.oam[index].priority_flag;
+        let x = self.fifo.x as i16;
+
+        for column in left..left + 8 {
+            if column < x {
+                continue;
+            }
+            let slot = (column - x) as usize;
+            while self.fifo.sprites.len() <= slot {
+                self.fifo.sprites.push_back(TRANSPARENT);
+            }
+            // whatever got there first has priority
+            if self.fifo.sprites[slot].colour == 0 {
+                self.fifo.sprites[slot] = SpritePixel {
+                    colour: self.sprite_pixel(index, column),
+                    palette,
+                    priority,
+                };
+            }
+        }
+    }
+
+    fn fifo_shift_out(&mut self) {
+        let Some(colour) = self.fifo.background.pop_front() else {
+            return;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+
+        let sprite = self.fifo.sprites.pop_front().unwrap_or(TRANSPARENT);
+        let pixel = if sprite.colour != 0
+            && self.lcdc.sprite_display
+            && !(sprite.priority && colour != 0)
+        {
+            let palette = if sprite.palette == 0 {
+                self.obp0
+            } else {
+                self.obp1
+            };
+            shade(palette, sprite.colour)
+        } else {
+            shade(self.bgp, colour)
+        };
+
+        let line = self.line as usize;
+        self.framebuffer[line * SCREEN_WIDTH + self.fifo.x] = pixel;
+        self.fifo.x += 1;
+        if self.fifo.done() && self.fifo.window_active {
+            self.window_line += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu::{Mode, Renderer, VRAM};
+
+    fn scene(renderer: Renderer) -> VRAM {
+        let mut vram = VRAM::new();
+        vram.set_renderer(renderer);
+        // a few different tiles scattered over both maps
+        for tile in 0..4usize {
+            for row in 0..16 {
+                vram.write(0x8000 + tile * 16 + row, (tile * 0x35 + row * 0x1B) as u8);
+            }
+        }
+        for i in 0..0x800 {
+            vram.write(0x9800 + i, (i * 7 % 4) as u8);
+        }
+        for (index, (y, x, flags)) in [
+            (20, 30, 0x00),
+            (24, 34, 0x90),
+            (60, 5, 0x20),
+            (100, 164, 0x40),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            for (offset, value) in [y, x, (index % 4) as u8, flags].into_iter().enumerate() {
+                vram.write_oam(index * 4 + offset, value);
+            }
+        }
+        vram.write_register(0xFF42, 13);
+        vram.write_register(0xFF43, 5);
+        vram.write_register(0xFF47, 0xE4);
+        vram.write_register(0xFF48, 0xD2);
+        vram.write_register(0xFF49, 0x1B);
+        vram.write_register(0xFF4A, 70);
+        vram.write_register(0xFF4B, 90);
+        vram.write_register(0xFF40, 0xF3);
+        vram
+    }
+
+    #[test]
+    fn test_matches_scanline_renderer() {
+        let mut scanline = scene(Renderer::Scanline);
+        let mut fifo = scene(Renderer::Fifo);
+        scanline.go(456 * 154);
+        fifo.go(456 * 154);
+        assert!(scanline.framebuffer() == fifo.framebuffer());
+    }
+
+    // dots from the start of mode 3 until HBlank on the first line
+    fn mode_3_length(vram: &mut VRAM) -> u32 {
+        vram.go(80);
+        let mut dots = 0;
+        while vram.mode() == Mode::Drawing {
+            vram.go(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    fn plain(scx: u8) -> VRAM {
+        let mut vram = VRAM::new();
+        vram.set_renderer(Renderer::Fifo);
+        vram.write_register(0xFF43, scx);
+        vram.write_register(0xFF40, 0x93);
+        vram
+    }
+
+    #[test]
+    fn test_mode_3_length() {
+        assert_eq!(mode_3_length(&mut plain(0)), 172);
+        assert_eq!(mode_3_length(&mut plain(3)), 175);
+
+        let mut vram = plain(0);
+        vram.write_oam(0, 16);
+        vram.write_oam(1, 50);
+        assert!(mode_3_length(&mut vram) >= 178);
+
+        let mut vram = plain(0);
+        vram.write_register(0xFF4B, 87);
+        vram.write_register(0xFF40, 0xB3);
+        assert_eq!(mode_3_length(&mut vram), 178);
+    }
+
+    #[test]
+    fn test_leftmost_sprite_wins() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut vram = VRAM::new();
+            vram.set_renderer(renderer);
+            // tile 1 is colour 1 all over, tile 2 colour 3
+            for row in 0..8 {
+                vram.write(0x8010 + row * 2, 0xFF);
+                vram.write(0x8020 + row * 2, 0xFF);
+                vram.write(0x8021 + row * 2, 0xFF);
+            }
+            // the first in OAM starts further right, so it loses where they overlap
+            for (offset, value) in [16, 6, 1, 0, 16, 4, 2, 0].into_iter().enumerate() {
+                vram.write_oam(offset, value);
+            }
+            vram.write_register(0xFF48, 0xE4);
+            vram.write_register(0xFF40, 0x93);
+            vram.go(456);
+
+            let line = &vram.framebuffer()[..8];
+            assert_eq!(line, &[3, 3, 3, 3, 1, 1, 0, 0], "{:?}", renderer);
+        }
+    }
+
+    #[test]
+    fn test_mid_line_palette_change() {
+        let mut vram = plain(0);
+        for i in 0..16 {
+            vram.write(0x8000 + i, 0xFF);
+        }
+        vram.write_register(0xFF47, 0x40);
+        // 80 dots of OAM scan, 12 of setup, then 40 pixels
+        vram.go(80 + 12 + 40);
+        vram.write_register(0xFF47, 0xC0);
+        vram.go(456);
+
+        let line = &vram.framebuffer()[..160];
+        assert!(line[..39].iter().all(|pixel| *pixel == 1));
+        assert!(line[41..].iter().all(|pixel| *pixel == 3));
+    }
+}
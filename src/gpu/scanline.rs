@@ -0,0 +1,422 @@
+use super::{SCREEN_WIDTH, VRAM};
+
+impl VRAM {
+    /// Draws the whole of the current line in one go at the end of mode 3. Fast, but
+    /// anything changed mid-line only shows up on the next one.
+    pub(super) fn render_scanline(&mut self) {
+        let line = self.line as usize;
+        let mut colours = [0u8; SCREEN_WIDTH];
+
+        // on DMG the BG enable bit blanks the window as well
+        if self.lcdc.bg_display {
+            self.render_background(&mut colours);
+            self.render_window(&mut colours);
+        }
+
+        let mut pixels = colours.map(|colour| shade(self.bgp, colour));
+        if self.lcdc.sprite_display {
+            self.render_sprites(&colours, &mut pixels);
+        }
+
+        self.framebuffer[line * SCREEN_WIDTH..(line + 1) * SCREEN_WIDTH].copy_from_slice(&pixels);
+    }
+
+    pub(super) fn sprite_height(&self) -> u8 {
+        if self.lcdc.sprite_size {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// The OAM scan: the first ten sprites in OAM order that cover the current line.
+    pub(super) fn line_sprites(&self) -> Vec<usize> {
+        let line = self.line as i16;
+        let height = self.sprite_height() as i16;
+        (0..self.oam.len())
+            .filter(|&index| {
+                let top = self.oam[index].y as i16 - 16;
+                (top..top + height).contains(&line)
+            })
+            .take(10)
+            .collect()
+    }
+
+    // colour index of a sprite's pixel at screen column `x`, 0 being transparent
+    pub(super) fn sprite_pixel(&self, index: usize, x: i16) -> u8 {
+        let sprite = &self.oam[index];
+        let height = self.sprite_height();
+
+        let mut column = (x - (sprite.x as i16 - 8)) as u8;
+        let mut row = (self.line as i16 - (sprite.y as i16 - 16)) as u8;
+        if sprite.x_flip_flag {
+            column = 7 - column;
+        }
+        if sprite.y_flip_flag {
+            row = height - 1 - row;
+        }
+
+        // tall sprites ignore the tile's low bit and run on into the next tile
+        let tile_number = if height == 16 {
+            sprite.tile_number & 0xFE
+        } else {
+            sprite.tile_number
+        };
+        self.tile_pixel(0x8000 + tile_number as usize * 16, column, row)
+    }
+
+    fn render_sprites(&self, colours: &[u8; SCREEN_WIDTH], pixels: &mut [u8; SCREEN_WIDTH]) {
+        // on DMG the leftmost sprite wins, OAM order breaking ties
+        let mut sprites = self.line_sprites();
+        sprites.sort_by_key(|&index| self.oam[index].x);
+
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            let x = x as i16;
+            let winner = sprites.iter().find_map(|&index| {
+                let left = self.oam[index].x as i16 - 8;
+                if !(left..left + 8).contains(&x) {
+                    return None;
+                }
+                match self.sprite_pixel(index, x) {
+                    0 => None,
+                    colour => Some((index, colour)),
+                }
+            });
+
+            let Some((index, colour)) = winner else {
+                continue;
+            };
+            let sprite = &self.oam[index];
+            // BG-over-OBJ only lets colours 1-3 of the background through
+            if sprite.priority_flag && colours[x as usize] != 0 {
+                continue;
+            }
+            let palette = if sprite.pallete_number == 0 {
+                self.obp0
+            } else {
+                self.obp1
+            };
+            *pixel = shade(palette, colour);
+        }
+    }
+
+    fn render_background(&self, pixels: &mut [u8; SCREEN_WIDTH]) {
+        let map = if self.lcdc.bg_tile_map_select {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let y = self.line.wrapping_add(self.scy);
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            let x = (x as u8).wrapping_add(self.scx);
+            *pixel = self.map_pixel(map, x, y);
+        }
+    }
+
+    fn render_window(&mut self, pixels: &mut [u8; SCREEN_WIDTH]) {
+        // WX is offset by 7, anything past the right edge never shows
+        if !self.lcdc.window_display_toggle || !self.window_triggered || self.wx > 166 {
+            return;
+        }
+
+        let map = if self.lcdc.window_tile_map_select {
+            0x9C00
+        } else {
+            0x9800
+        };
+        let start = self.wx as usize;
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            if x + 7 < start {
+                continue;
+            }
+            *pixel = self.map_pixel(map, (x + 7 - start) as u8, self.window_line);
+        }
+        self.window_line += 1;
+    }
+
+    // colour index of pixel x, y of the 256x256 picture a tile map makes up
+    pub(super) fn map_pixel(&self, map: usize, x: u8, y: u8) -> u8 {
+        let tile_index = (y as usize / 8) * 32 + x as usize / 8;
+        let tile_number = self.read(map + tile_index);
+        self.tile_pixel(self.tile_address(tile_number), x % 8, y % 8)
+    }
+
+    pub(super) fn tile_address(&self, tile_number: u8) -> usize {
+        if self.lcdc.bg_window_tile_select {
+            0x8000 + tile_number as usize * 16
+        } else {
+            // the other addressing mode counts signed from 0x9000
+            (0x9000 + (tile_number as i8 as isize) * 16) as usize
+        }
+    }
+
+    pub(super) fn tile_pixel(&self, tile: usize, x: u8, y: u8) -> u8 {
+        let low = self.read(tile + y as usize * 2);
+        let high = self.read(tile + y as usize * 2 + 1);
+        let bit = 7 - x;
+        (((high >> bit) & 0x01) << 1) | ((low >> bit) & 0x01)
+    }
+}
+
+// BGP, OBP0 and OBP1 hold a 2-bit shade for each colour index
+pub(super) fn shade(palette: u8, colour: u8) -> u8 {
+    (palette >> (colour * 2)) & 0x03
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gpu::{SCREEN_WIDTH, VRAM};
+
+    // a tile where every pixel is `colour`
+    fn solid_tile(vram: &mut VRAM, address: usize, colour: u8) {
+        for row in 0..8 {
+            vram.write(
+                address + row * 2,
+                if colour & 0x01 != 0 { 0xFF } else { 0x00 },
+            );
+            vram.write(
+                address + row * 2 + 1,
+                if colour & 0x02 != 0 { 0xFF } else { 0x00 },
+            );
+        }
+    }
+
+    // palettes that map every colour onto the shade of the same number
+    fn identity_palettes() -> VRAM {
+        let mut vram = VRAM::new();
+        vram.write_register(0xFF47, 0xE4);
+        vram.write_register(0xFF48, 0xE4);
+        vram.write_register(0xFF49, 0xE4);
+        vram
+    }
+
+    fn draw_frame(vram: &mut VRAM) {
+        vram.go(456 * 154);
+    }
+
+    fn pixel(vram: &VRAM, x: usize, y: usize) -> u8 {
+        vram.framebuffer()[y * SCREEN_WIDTH + x]
+    }
+
+    #[test]
+    fn test_background_unsigned_tiles() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8010, 3);
+        vram.write(0x9800, 1);
+        vram.write_register(0xFF40, 0x91);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 0, 0), 3);
+        assert_eq!(pixel(&vram, 7, 7), 3);
+        assert_eq!(pixel(&vram, 8, 0), 0);
+        assert_eq!(pixel(&vram, 0, 8), 0);
+    }
+
+    #[test]
+    fn test_background_signed_tiles() {
+        let mut vram = identity_palettes();
+        // tile -1 sits just below 0x9000
+        solid_tile(&mut vram, 0x8FF0, 2);
+        vram.write(0x9C00, 0xFF);
+        vram.write_register(0xFF40, 0x89);
+        draw_frame(&mut vram);
+        assert_eq!(pixel(&vram, 0, 0), 2);
+    }
+
+    #[test]
+    fn test_tile_pixel_bits() {
+        let mut vram = identity_palettes();
+        vram.write(0x8000, 0b1000_0001);
+        vram.write(0x8001, 0b1100_0000);
+        assert_eq!(vram.tile_pixel(0x8000, 0, 0), 3);
+        assert_eq!(vram.tile_pixel(0x8000, 1, 0), 2);
+        assert_eq!(vram.tile_pixel(0x8000, 2, 0), 0);
+        assert_eq!(vram.tile_pixel(0x8000, 7, 0), 1);
+    }
+
+    #[test]
+    fn test_scroll_wraps() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8010, 1);
+        // bottom right corner of the map
+        vram.write(0x9800 + 31 * 32 + 31, 1);
+        vram.write_register(0xFF42, 0xFC);
+        vram.write_register(0xFF43, 0xFC);
+        vram.write_register(0xFF40, 0x91);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 3, 3), 1);
+        assert_eq!(pixel(&vram, 4, 3), 0);
+        assert_eq!(pixel(&vram, 3, 4), 0);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8010, 2);
+        for i in 0..0x400 {
+            vram.write(0x9C00 + i, 1);
+        }
+        vram.write_register(0xFF4A, 10);
+        vram.write_register(0xFF4B, 27);
+        vram.write_register(0xFF40, 0xF1);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 19, 10), 0);
+        assert_eq!(pixel(&vram, 20, 10), 2);
+        assert_eq!(pixel(&vram, 20, 9), 0);
+        assert_eq!(pixel(&vram, 159, 143), 2);
+    }
+
+    #[test]
+    fn test_window_line_counter_skips_hidden_lines() {
+        let mut vram = identity_palettes();
+        // only the window's second tile row has a colour
+        solid_tile(&mut vram, 0x8010, 1);
+        vram.write(0x9C20, 1);
+        vram.write_register(0xFF40, 0xF1);
+
+        // hide the window for lines 0-7 by pushing it off screen, then bring it back
+        vram.write_register(0xFF4B, 200);
+        vram.go(456 * 8);
+        vram.write_register(0xFF4B, 7);
+        vram.go(456 * 146);
+
+        // the window resumes at its own line 0, not line 8
+        assert_eq!(pixel(&vram, 0, 8), 0);
+        assert_eq!(pixel(&vram, 0, 16), 1);
+    }
+
+    #[test]
+    fn test_bg_disabled_is_blank() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8000, 3);
+        vram.write_register(0xFF40, 0x90);
+        draw_frame(&mut vram);
+        assert!(vram.framebuffer().iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn test_lcdc_reads_back() {
+        let mut vram = identity_palettes();
+        vram.write_register(0xFF40, 0xA5);
+        assert_eq!(vram.read_register(0xFF40), 0xA5);
+    }
+
+    fn sprite(vram: &mut VRAM, index: usize, y: u8, x: u8, tile: u8, flags: u8) {
+        for (offset, value) in [y, x, tile, flags].into_iter().enumerate() {
+            vram.write_oam(index * 4 + offset, value);
+        }
+    }
+
+    #[test]
+    fn test_bg_palette() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8010, 1);
+        vram.write(0x9800, 1);
+        vram.write_register(0xFF47, 0x1B);
+        vram.write_register(0xFF40, 0x91);
+        draw_frame(&mut vram);
+        assert_eq!(pixel(&vram, 0, 0), 2);
+        assert_eq!(pixel(&vram, 8, 0), 3);
+    }
+
+    #[test]
+    fn test_sprite_position_and_palette() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8020, 1);
+        sprite(&mut vram, 0, 16, 8, 2, 0x00);
+        sprite(&mut vram, 1, 16, 20, 2, 0x10);
+        vram.write_register(0xFF49, 0xEC);
+        vram.write_register(0xFF40, 0x83);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 0, 0), 1);
+        assert_eq!(pixel(&vram, 7, 7), 1);
+        assert_eq!(pixel(&vram, 8, 0), 0);
+        assert_eq!(pixel(&vram, 0, 8), 0);
+        assert_eq!(pixel(&vram, 12, 0), 3);
+    }
+
+    #[test]
+    fn test_ten_sprites_per_line() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8010, 3);
+        for index in 0..12 {
+            sprite(&mut vram, index, 16, 8 + index as u8 * 8, 1, 0x00);
+        }
+        vram.write_register(0xFF40, 0x83);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 72, 0), 3);
+        assert_eq!(pixel(&vram, 80, 0), 0);
+        assert_eq!(vram.line_sprites().len(), 10);
+    }
+
+    #[test]
+    fn test_lower_x_wins() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8010, 1);
+        solid_tile(&mut vram, 0x8020, 2);
+        // later in OAM but further left
+        sprite(&mut vram, 0, 16, 12, 1, 0x00);
+        sprite(&mut vram, 1, 16, 10, 2, 0x00);
+        // same X, OAM order decides
+        sprite(&mut vram, 2, 32, 8, 1, 0x00);
+        sprite(&mut vram, 3, 32, 8, 2, 0x00);
+        vram.write_register(0xFF40, 0x83);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 4, 0), 2);
+        assert_eq!(pixel(&vram, 10, 0), 1);
+        assert_eq!(pixel(&vram, 0, 16), 1);
+    }
+
+    #[test]
+    fn test_bg_over_obj() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8010, 2);
+        solid_tile(&mut vram, 0x8020, 1);
+        // background colour 1 on the left tile, colour 0 on the right
+        vram.write(0x9800, 2);
+        sprite(&mut vram, 0, 16, 12, 1, 0x80);
+        vram.write_register(0xFF40, 0x93);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 4, 0), 1);
+        assert_eq!(pixel(&vram, 8, 0), 2);
+    }
+
+    #[test]
+    fn test_flips() {
+        let mut vram = identity_palettes();
+        // only the top left pixel is set
+        vram.write(0x8010, 0x80);
+        sprite(&mut vram, 0, 16, 8, 1, 0x00);
+        sprite(&mut vram, 1, 16, 16, 1, 0x20);
+        sprite(&mut vram, 2, 16, 24, 1, 0x40);
+        vram.write_register(0xFF40, 0x83);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 0, 0), 1);
+        assert_eq!(pixel(&vram, 15, 0), 1);
+        assert_eq!(pixel(&vram, 16, 7), 1);
+        assert_eq!(pixel(&vram, 16, 0), 0);
+    }
+
+    #[test]
+    fn test_tall_sprites_ignore_tile_bit_0() {
+        let mut vram = identity_palettes();
+        solid_tile(&mut vram, 0x8020, 1);
+        solid_tile(&mut vram, 0x8030, 2);
+        sprite(&mut vram, 0, 16, 8, 3, 0x00);
+        sprite(&mut vram, 1, 16, 16, 2, 0x40);
+        vram.write_register(0xFF40, 0x87);
+        draw_frame(&mut vram);
+
+        assert_eq!(pixel(&vram, 0, 0), 1);
+        assert_eq!(pixel(&vram, 0, 15), 2);
+        assert_eq!(pixel(&vram, 8, 0), 2);
+        assert_eq!(pixel(&vram, 8, 15), 1);
+    }
+}
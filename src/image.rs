@@ -0,0 +1,92 @@
+use crate::utility::{file::write_atomic, png};
+use std::{io, path::Path};
+
+/// An RGB image, three bytes a pixel in rows from the top left.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<u8>) -> Image {
+        assert_eq!(
+            pixels.len(),
+            width * height * 3,
+            "pixel data doesn't match the image size, this must be a programming error"
+        );
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let index = (y * self.width + x) * 3;
+        [
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        let index = (y * self.width + x) * 3;
+        self.pixels[index..index + 3].copy_from_slice(&rgb);
+    }
+
+    /// Blown up by a whole number so every pixel stays a sharp square.
+    pub fn scaled(&self, scale: usize) -> Image {
+        assert!(scale > 0, "can't scale an image by 0");
+        let mut pixels = Vec::with_capacity(self.pixels.len() * scale * scale);
+        for row in self.pixels.chunks(self.width * 3) {
+            let mut scaled_row = Vec::with_capacity(row.len() * scale);
+            for pixel in row.chunks(3) {
+                for _ in 0..scale {
+                    scaled_row.extend_from_slice(pixel);
+                }
+            }
+            for _ in 0..scale {
+                pixels.extend_from_slice(&scaled_row);
+            }
+        }
+        Image::new(self.width * scale, self.height * scale, pixels)
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode(self.width as u32, self.height as u32, &self.pixels)
+    }
+
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        write_atomic(path, &self.to_png())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scaled() {
+        let image = Image::new(2, 1, vec![1, 2, 3, 4, 5, 6]);
+        let scaled = image.scaled(3);
+        assert_eq!((scaled.width(), scaled.height()), (6, 3));
+        assert_eq!(scaled.pixel(2, 2), [1, 2, 3]);
+        assert_eq!(scaled.pixel(3, 0), [4, 5, 6]);
+        assert_eq!(image.scaled(1), image);
+    }
+}
@@ -0,0 +1,597 @@
+use crate::cartridge::{self, CartridgeInfo, GbxFooter, HeaderError};
+use std::fmt::Write;
+
+/// Everything `gb info` reports about a ROM, straight from the header at 0x0100..=0x014F.
+#[derive(Debug, Clone)]
+pub struct RomInfo {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub cartridge: CartridgeInfo,
+    pub licensee: String,
+    pub licensee_name: Option<&'static str>,
+    pub japanese: bool,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    pub global_checksum_valid: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    Enhanced,
+    Only,
+}
+
+impl RomInfo {
+    /// Reads the header of `rom`. A GBX footer is stripped and applied the same way
+    /// the emulator itself does it.
+    pub fn from_rom(rom: &[u8]) -> Result<RomInfo, HeaderError> {
+        let mut rom = rom.to_vec();
+        let gbx = GbxFooter::split(&mut rom);
+
+        let mut cartridge = CartridgeInfo::from_header(&rom)?;
+        match &gbx {
+            Some(gbx) => cartridge.apply_gbx(gbx),
+            None => {
+                if let Some(mapper) = cartridge::detect_unlicensed(&rom) {
+                    cartridge.mapper = mapper;
+                }
+            }
+        }
+
+        let cgb_support = match rom[0x0143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        // newer carts gave the last bytes of the title over to a manufacturer code
+        let manufacturer_code = &rom[0x013F..0x0143];
+        let manufacturer_code = if cgb_support != CgbSupport::None
+            && manufacturer_code
+                .iter()
+                .all(|byte| byte.is_ascii_uppercase())
+        {
+            Some(String::from_utf8_lossy(manufacturer_code).into_owned())
+        } else {
+            None
+        };
+        let title_end = if manufacturer_code.is_some() {
+            0x013F
+        } else {
+            0x0143
+        };
+        let title = rom[0x0134..title_end]
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        let (licensee, licensee_name) = match rom[0x014B] {
+            0x33 => {
+                let code = String::from_utf8_lossy(&rom[0x0144..0x0146]).into_owned();
+                let name = new_licensee(&code);
+                (code, name)
+            }
+            code => (format!("{:02X}", code), old_licensee(code)),
+        };
+
+        let header_checksum = rom[0x014D];
+        let global_checksum = u16::from_be_bytes([rom[0x014E], rom[0x014F]]);
+
+        Ok(RomInfo {
+            title,
+            manufacturer_code,
+            cgb_support,
+            sgb_support: rom[0x0146] == 0x03,
+            cartridge,
+            licensee,
+            licensee_name,
+            japanese: rom[0x014A] == 0x00,
+            version: rom[0x014C],
+            header_checksum,
+            header_checksum_valid: compute_header_checksum(&rom) == header_checksum,
+            global_checksum,
+            global_checksum_valid: compute_global_checksum(&rom) == global_checksum,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        let valid = |value: bool| if value { "valid" } else { "INVALID" };
+
+        let mut text = String::new();
+        let _ = writeln!(text, "Title:             {}", self.title);
+        let _ = writeln!(
+            text,
+            "Manufacturer code: {}",
+            self.manufacturer_code.as_deref().unwrap_or("-")
+        );
+        let _ = writeln!(text, "CGB support:       {}", self.cgb_support.name());
+        let _ = writeln!(text, "SGB support:       {}", yes_no(self.sgb_support));
+        let _ = writeln!(
+            text,
+            "Cartridge type:    {:02X} ({})",
+            self.cartridge.cartridge_type, self.cartridge.mapper
+        );
+        let _ = writeln!(
+            text,
+            "Battery:           {}",
+            yes_no(self.cartridge.has_battery)
+        );
+        let _ = writeln!(
+            text,
+            "RTC:               {}",
+            yes_no(self.cartridge.has_rtc)
+        );
+        let _ = writeln!(
+            text,
+            "Rumble:            {}",
+            yes_no(self.cartridge.has_rumble)
+        );
+        let _ = match self.cartridge.rom_size {
+            Some(rom_size) => writeln!(text, "ROM size:          {} KiB", rom_size / 1024),
+            None => writeln!(text, "ROM size:          unknown"),
+        };
+        let _ = writeln!(
+            text,
+            "RAM size:          {} KiB",
+            self.cartridge.ram_size / 1024
+        );
+        let _ = writeln!(
+            text,
+            "Licensee:          {} ({})",
+            self.licensee,
+            self.licensee_name.unwrap_or("unknown")
+        );
+        let _ = writeln!(text, "Destination:       {}", self.destination());
+        let _ = writeln!(text, "Version:           {}", self.version);
+        let _ = writeln!(
+            text,
+            "Header checksum:   {:02X} ({})",
+            self.header_checksum,
+            valid(self.header_checksum_valid)
+        );
+        let _ = writeln!(
+            text,
+            "Global checksum:   {:04X} ({})",
+            self.global_checksum,
+            valid(self.global_checksum_valid)
+        );
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let string = |value: &str| format!("\"{}\"", escape_json(value));
+        let optional = |value: Option<&str>| value.map_or("null".to_string(), string);
+
+        let fields = [
+            ("title", string(&self.title)),
+            (
+                "manufacturer_code",
+                optional(self.manufacturer_code.as_deref()),
+            ),
+            ("cgb_support", string(self.cgb_support.name())),
+            ("sgb_support", self.sgb_support.to_string()),
+            ("cartridge_type", self.cartridge.cartridge_type.to_string()),
+            ("mapper", string(&self.cartridge.mapper.to_string())),
+            ("battery", self.cartridge.has_battery.to_string()),
+            ("rtc", self.cartridge.has_rtc.to_string()),
+            ("rumble", self.cartridge.has_rumble.to_string()),
+            (
+                "rom_size",
+                self.cartridge
+                    .rom_size
+                    .map_or("null".to_string(), |rom_size| rom_size.to_string()),
+            ),
+            ("ram_size", self.cartridge.ram_size.to_string()),
+            ("licensee", string(&self.licensee)),
+            ("licensee_name", optional(self.licensee_name)),
+            ("destination", string(self.destination())),
+            ("version", self.version.to_string()),
+            ("header_checksum", self.header_checksum.to_string()),
+            (
+                "header_checksum_valid",
+                self.header_checksum_valid.to_string(),
+            ),
+            ("global_checksum", self.global_checksum.to_string()),
+            (
+                "global_checksum_valid",
+                self.global_checksum_valid.to_string(),
+            ),
+        ];
+
+        let body = fields
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value))
+            .collect::<Vec<_>>()
+            .join(",\n");
+        format!("{{\n{}\n}}\n", body)
+    }
+
+    fn destination(&self) -> &'static str {
+        if self.japanese {
+            "Japan"
+        } else {
+            "Overseas"
+        }
+    }
+}
+
+impl CgbSupport {
+    fn name(&self) -> &'static str {
+        match self {
+            CgbSupport::None => "none",
+            CgbSupport::Enhanced => "enhanced",
+            CgbSupport::Only => "CGB only",
+        }
+    }
+}
+
+// the boot ROM refuses to start a cart when this one is wrong
+pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+    rom[0x0134..=0x014C].iter().fold(0u8, |checksum, byte| {
+        checksum.wrapping_sub(*byte).wrapping_sub(1)
+    })
+}
+
+// nothing checks this one on hardware, but a mismatch usually means a bad dump or a hack
+pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(address, _)| *address != 0x014E && *address != 0x014F)
+        .fold(0u16, |checksum, (_, byte)| {
+            checksum.wrapping_add(*byte as u16)
+        })
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn old_licensee(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x00 => "None",
+        0x01 => "Nintendo",
+        0x08 => "Capcom",
+        0x09 => "Hot-B",
+        0x0A => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C => "Elite Systems",
+        0x13 => "EA (Electronic Arts)",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F => "Virgin Games",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kemco",
+        0x29 => "SETA Corporation",
+        0x30 => "Infogrames",
+        0x31 => "Nintendo",
+        0x32 => "Bandai",
+        0x34 => "Konami",
+        0x35 => "HectorSoft",
+        0x38 => "Capcom",
+        0x39 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 => "Atlus",
+        0x44 => "Malibu Interactive",
+        0x46 => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4A => "Virgin Games",
+        0x4D => "Malibu Interactive",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA Corporation",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley Company",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x61 => "Virgin Games",
+        0x67 => "Ocean Software",
+        0x69 => "EA (Electronic Arts)",
+        0x6E => "Elite Systems",
+        0x6F => "Electro Brain",
+        0x70 => "Infogrames",
+        0x71 => "Interplay Entertainment",
+        0x72 => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve Limited",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x7F => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai Corp.",
+        0x8E => "Ape Inc.",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kemco",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9D => "Banpresto",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA2 => "Bandai",
+        0xA4 => "Konami",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAA => "Broderbund",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB0 => "Acclaim Entertainment",
+        0xB1 => "ASCII Corporation or Nexsoft",
+        0xB2 => "Bandai",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy Corporation",
+        0xC0 => "Taito",
+        0xC2 => "Kemco",
+        0xC3 => "Square",
+        0xC4 => "Tokuma Shoten",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP, Inc.",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xCE => "Pony Canyon",
+        0xCF => "Angel",
+        0xD0 => "Taito",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD6 => "Naxat Soft",
+        0xD7 => "Copya System",
+        0xD9 => "Banpresto",
+        0xDA => "Tomy",
+        0xDB => "LJN",
+        0xDD => "Nippon Computer Systems",
+        0xDE => "Human Ent.",
+        0xDF => "Altron",
+        0xE0 => "Jaleco",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE3 => "Varie",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEB => "Atlus",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        0xFF => "LJN",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn new_licensee(code: &str) -> Option<&'static str> {
+    let name = match code {
+        "00" => "None",
+        "01" => "Nintendo Research & Development 1",
+        "08" => "Capcom",
+        "13" => "EA (Electronic Arts)",
+        "18" => "Hudson Soft",
+        "19" => "B-AI",
+        "20" => "KSS",
+        "22" => "Planning Office WADA",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco",
+        "29" => "SETA Corporation",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean Software/Acclaim Entertainment",
+        "34" => "Konami",
+        "35" => "HectorSoft",
+        "37" => "Taito",
+        "38" => "Hudson Soft",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu Interactive",
+        "46" => "Angel",
+        "47" => "Bullet-Proof Software",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim Entertainment",
+        "52" => "Activision",
+        "53" => "Sammy USA Corporation",
+        "54" => "Konami",
+        "55" => "Hi Tech Expressions",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley Company",
+        "60" => "Titus Interactive",
+        "61" => "Virgin Games",
+        "64" => "Lucasfilm Games",
+        "67" => "Ocean Software",
+        "69" => "EA (Electronic Arts)",
+        "70" => "Infogrames",
+        "71" => "Interplay Entertainment",
+        "72" => "Broderbund",
+        "73" => "Sculptured Software",
+        "75" => "The Sales Curve Limited",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa Entertainment",
+        "83" => "LOZC G.",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft Co.",
+        "92" => "Video System",
+        "93" => "Ocean Software/Acclaim Entertainment",
+        "95" => "Varie",
+        "96" => "Yonezawa/S'Pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        "9H" => "Bottom Up",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        "BL" => "MTO",
+        "DK" => "Kodansha",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Mapper;
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0134..0x013F].copy_from_slice(b"POCKETGAMES");
+        rom[0x013F..0x0143].copy_from_slice(b"APXE");
+        rom[0x0143] = 0x80;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x0146] = 0x03;
+        rom[0x0147] = 0x1B;
+        rom[0x0148] = 0x01;
+        rom[0x0149] = 0x02;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x33;
+        rom[0x014D] = compute_header_checksum(&rom);
+        let global = compute_global_checksum(&rom).to_be_bytes();
+        rom[0x014E..0x0150].copy_from_slice(&global);
+        rom
+    }
+
+    #[test]
+    fn test_header_fields() {
+        let info = RomInfo::from_rom(&rom()).unwrap();
+        assert_eq!(info.title, "POCKETGAMES");
+        assert_eq!(info.manufacturer_code.as_deref(), Some("APXE"));
+        assert_eq!(info.cgb_support, CgbSupport::Enhanced);
+        assert!(info.sgb_support);
+        assert_eq!(info.cartridge.mapper, Mapper::MBC5);
+        assert_eq!(info.cartridge.rom_size, Some(0x10000));
+        assert_eq!(info.cartridge.ram_size, 0x2000);
+        assert_eq!(info.licensee, "01");
+        assert_eq!(
+            info.licensee_name,
+            Some("Nintendo Research & Development 1")
+        );
+        assert_eq!(info.destination(), "Overseas");
+        assert!(info.header_checksum_valid);
+        assert!(info.global_checksum_valid);
+    }
+
+    #[test]
+    fn test_dmg_title_includes_manufacturer_bytes() {
+        let mut rom = rom();
+        rom[0x0143] = 0x00;
+        rom[0x014B] = 0x01;
+        let info = RomInfo::from_rom(&rom).unwrap();
+        assert_eq!(info.title, "POCKETGAMESAPXE");
+        assert_eq!(info.manufacturer_code, None);
+        assert_eq!(info.licensee_name, Some("Nintendo"));
+    }
+
+    #[test]
+    fn test_bad_checksums() {
+        let mut rom = rom();
+        rom[0x0200] = 0x12;
+        let info = RomInfo::from_rom(&rom).unwrap();
+        assert!(info.header_checksum_valid);
+        assert!(!info.global_checksum_valid);
+
+        rom[0x0134] = b'Q';
+        assert!(!RomInfo::from_rom(&rom).unwrap().header_checksum_valid);
+    }
+
+    #[test]
+    fn test_malformed_roms() {
+        assert!(matches!(
+            RomInfo::from_rom(&[0u8; 0x20]),
+            Err(HeaderError::TooShort(0x20))
+        ));
+
+        // long enough with the footer, too short without it
+        let mut gbx = vec![0u8; 0x0120];
+        gbx.extend_from_slice(b"MBC5\0\0\0\0");
+        gbx.extend_from_slice(&[0u8; 0x28]);
+        gbx.extend_from_slice(&0x40u32.to_be_bytes());
+        gbx.extend_from_slice(&1u32.to_be_bytes());
+        gbx.extend_from_slice(&0u32.to_be_bytes());
+        gbx.extend_from_slice(b"GBX!");
+        assert!(matches!(
+            RomInfo::from_rom(&gbx),
+            Err(HeaderError::TooShort(0x0120))
+        ));
+
+        let mut rom = rom();
+        rom[0x0148] = 0xFF;
+        let info = RomInfo::from_rom(&rom).unwrap();
+        assert_eq!(info.cartridge.rom_size, None);
+        assert!(info.to_text().contains("ROM size:          unknown"));
+        assert!(info.to_json().contains("\"rom_size\": null"));
+    }
+
+    #[test]
+    fn test_json() {
+        let mut rom = rom();
+        rom[0x0134] = b'"';
+        let json = RomInfo::from_rom(&rom).unwrap().to_json();
+        assert!(json.starts_with("{\n  \"title\": \"\\\"OCKETGAMES\",\n"));
+        assert!(json.contains("  \"mapper\": \"MBC5\",\n"));
+        assert!(json.contains("  \"licensee_name\": \"Nintendo Research & Development 1\",\n"));
+        assert!(json.ends_with("  \"global_checksum_valid\": false\n}\n"));
+    }
+}
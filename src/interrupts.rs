@@ -1,3 +1,4 @@
+use crate::cpu::Interrupt;
 use crate::utility::convenience;
 pub struct Interrupts {
     vblank_interrupt: bool,
@@ -49,4 +50,36 @@ impl Interrupts {
             false,
         ])
     }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        match interrupt {
+            Interrupt::VBlank => self.vblank_interrupt = true,
+            Interrupt::LCD => self.lcd_interrupt = true,
+            Interrupt::Timer => self.timer_interrupt = true,
+            Interrupt::Joypad => self.joypad_interrupt = true,
+        }
+    }
+
+    // IF at 0xFF0F, the unused top bits read back set
+    pub fn set_flags(&mut self, flags: u8) {
+        let flags = convenience::break_byte_into_flags(flags);
+        self.vblank_interrupt = flags[0];
+        self.lcd_interrupt = flags[1];
+        self.timer_interrupt = flags[2];
+        self.serial_interrupt = flags[3];
+        self.joypad_interrupt = flags[4];
+    }
+
+    pub fn get_flags(&self) -> u8 {
+        convenience::collapse_flags_into_byte([
+            self.vblank_interrupt,
+            self.lcd_interrupt,
+            self.timer_interrupt,
+            self.serial_interrupt,
+            self.joypad_interrupt,
+            true,
+            true,
+            true,
+        ])
+    }
 }
@@ -0,0 +1,331 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::utility::checksum::crc32;
+use crate::utility::inflate::inflate;
+
+const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The archive is damaged or uses a feature we can't read.
+    Archive(String),
+    NoRom,
+    /// More than one ROM in the archive and no entry was named.
+    MultipleRoms(Vec<String>),
+    EntryNotFound(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "{}", error),
+            LoadError::Archive(message) => write!(f, "{}", message),
+            LoadError::NoRom => write!(f, "archive doesn't contain a .gb or .gbc file"),
+            LoadError::MultipleRoms(names) => write!(
+                f,
+                "archive contains several ROMs, pick one with --entry: {}",
+                names.join(", ")
+            ),
+            LoadError::EntryNotFound(name) => write!(f, "archive has no entry named {}", name),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> LoadError {
+        LoadError::Io(error)
+    }
+}
+
+/// Reads a ROM from disk, unpacking it first if it's in a zip or gzip file.
+pub fn load_rom(path: &Path, entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    extract_rom(fs::read(path)?, entry)
+}
+
+/// Picks the ROM out of `data` based on its magic bytes, plain ROMs are passed through.
+pub fn extract_rom(data: Vec<u8>, entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        unzip(&data, entry)
+    } else if data.starts_with(&[0x1F, 0x8B]) {
+        gunzip(&data)
+    } else if data.starts_with(b"7z\xBC\xAF\x27\x1C") {
+        Err(LoadError::Archive(
+            "7z archives aren't supported, use zip or gzip".to_string(),
+        ))
+    } else {
+        Ok(data)
+    }
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom| extension.eq_ignore_ascii_case(rom))
+        })
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, LoadError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| LoadError::Archive("archive is truncated".to_string()))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, LoadError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| LoadError::Archive("archive is truncated".to_string()))
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, LoadError> {
+    if data.len() < 18 || data[2] != 8 {
+        return Err(LoadError::Archive(
+            "unsupported gzip compression".to_string(),
+        ));
+    }
+
+    let flags = data[3];
+    let mut offset = 10;
+    if flags & 0x04 != 0 {
+        offset += 2 + u16_at(data, offset)? as usize;
+    }
+    // original file name and comment are zero terminated
+    for flag in [0x08, 0x10] {
+        if flags & flag != 0 {
+            let length = data
+                .get(offset..)
+                .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+                .ok_or_else(|| LoadError::Archive("gzip header is truncated".to_string()))?;
+            offset += length + 1;
+        }
+    }
+    if flags & 0x02 != 0 {
+        offset += 2;
+    }
+
+    let stream = data
+        .get(offset..)
+        .ok_or_else(|| LoadError::Archive("gzip header is truncated".to_string()))?;
+    let (rom, used) = inflate(stream).map_err(LoadError::Archive)?;
+    let crc = u32_at(stream, used)?;
+    if crc != crc32(&rom) {
+        return Err(LoadError::Archive("gzip CRC mismatch".to_string()));
+    }
+    Ok(rom)
+}
+
+struct ZipEntry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    local_header: usize,
+}
+
+fn zip_entries(data: &[u8]) -> Result<Vec<ZipEntry>, LoadError> {
+    // the end of central directory record sits at the end, possibly followed by a comment
+    let end = (0..=data.len().saturating_sub(22))
+        .rev()
+        .find(|&offset| data[offset..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| LoadError::Archive("zip directory not found".to_string()))?;
+
+    let count = u16_at(data, end + 10)? as usize;
+    let mut offset = u32_at(data, end + 16)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(data, offset)? != 0x02014B50 {
+            return Err(LoadError::Archive("zip directory is damaged".to_string()));
+        }
+        let name_length = u16_at(data, offset + 28)? as usize;
+        let extra_length = u16_at(data, offset + 30)? as usize;
+        let comment_length = u16_at(data, offset + 32)? as usize;
+        let name = data
+            .get(offset + 46..offset + 46 + name_length)
+            .ok_or_else(|| LoadError::Archive("zip directory is truncated".to_string()))?;
+
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).to_string(),
+            method: u16_at(data, offset + 10)?,
+            crc: u32_at(data, offset + 16)?,
+            compressed_size: u32_at(data, offset + 20)? as usize,
+            local_header: u32_at(data, offset + 42)? as usize,
+        });
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+    Ok(entries)
+}
+
+fn unzip(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    let entries = zip_entries(data)?;
+    let entry = match entry {
+        Some(name) => entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| LoadError::EntryNotFound(name.to_string()))?,
+        None => {
+            let roms: Vec<&ZipEntry> = entries
+                .iter()
+                .filter(|entry| is_rom_name(&entry.name))
+                .collect();
+            match roms.as_slice() {
+                [] => return Err(LoadError::NoRom),
+                [rom] => *rom,
+                _ => {
+                    let names = roms.iter().map(|rom| rom.name.clone()).collect();
+                    return Err(LoadError::MultipleRoms(names));
+                }
+            }
+        }
+    };
+
+    let header = entry.local_header;
+    if u32_at(data, header)? != 0x04034B50 {
+        return Err(LoadError::Archive(
+            "zip entry header is damaged".to_string(),
+        ));
+    }
+    let start =
+        header + 30 + u16_at(data, header + 26)? as usize + u16_at(data, header + 28)? as usize;
+    let compressed = data
+        .get(start..start + entry.compressed_size)
+        .ok_or_else(|| LoadError::Archive("zip entry is truncated".to_string()))?;
+
+    let rom = match entry.method {
+        0 => compressed.to_vec(),
+        8 => inflate(compressed).map_err(LoadError::Archive)?.0,
+        method => {
+            return Err(LoadError::Archive(format!(
+                "{} uses unsupported zip compression method {}",
+                entry.name, method
+            )))
+        }
+    };
+    if crc32(&rom) != entry.crc {
+        return Err(LoadError::Archive(format!(
+            "{} fails its CRC check",
+            entry.name
+        )));
+    }
+    Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // builds a zip with stored (uncompressed) entries
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut directory = Vec::new();
+        for (name, contents) in files {
+            let offset = data.len() as u32;
+            let crc = crc32(contents);
+            let size = contents.len() as u32;
+
+            data.extend_from_slice(&0x04034B50u32.to_le_bytes());
+            data.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            data.extend_from_slice(&crc.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&[0, 0]);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(contents);
+
+            directory.extend_from_slice(&0x02014B50u32.to_le_bytes());
+            directory.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            directory.extend_from_slice(&crc.to_le_bytes());
+            directory.extend_from_slice(&size.to_le_bytes());
+            directory.extend_from_slice(&size.to_le_bytes());
+            directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+        }
+
+        let directory_offset = data.len() as u32;
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(&0x06054B50u32.to_le_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        data.extend_from_slice(&directory_offset.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_plain_rom_passes_through() {
+        assert_eq!(
+            extract_rom(vec![0x00, 0xC3], None).unwrap(),
+            vec![0x00, 0xC3]
+        );
+    }
+
+    #[test]
+    fn test_zip_picks_the_rom() {
+        let data = zip(&[("README.txt", b"hello"), ("Game.GBC", b"rom")]);
+        assert_eq!(extract_rom(data, None).unwrap(), b"rom".to_vec());
+    }
+
+    #[test]
+    fn test_zip_with_several_roms() {
+        let data = zip(&[("a.gb", b"one"), ("b.gb", b"two")]);
+        match extract_rom(data.clone(), None) {
+            Err(LoadError::MultipleRoms(names)) => assert_eq!(names, ["a.gb", "b.gb"]),
+            _ => panic!("expected MultipleRoms"),
+        }
+        assert_eq!(
+            extract_rom(data.clone(), Some("b.gb")).unwrap(),
+            b"two".to_vec()
+        );
+        assert!(matches!(
+            extract_rom(data, Some("c.gb")),
+            Err(LoadError::EntryNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_zip_without_rom() {
+        let data = zip(&[("README.txt", b"hello")]);
+        assert!(matches!(extract_rom(data, None), Err(LoadError::NoRom)));
+    }
+
+    #[test]
+    fn test_zip_crc_mismatch() {
+        let mut data = zip(&[("a.gb", b"one")]);
+        data[30 + 4] = b'X';
+        assert!(matches!(
+            extract_rom(data, None),
+            Err(LoadError::Archive(_))
+        ));
+    }
+
+    #[test]
+    fn test_gzip() {
+        // "abcabcabcabc" with a file name in the header
+        let mut data = vec![0x1F, 0x8B, 0x08, 0x08, 0, 0, 0, 0, 0x00, 0x03];
+        data.extend_from_slice(b"game.gb\0");
+        data.extend_from_slice(&[0x4B, 0x4C, 0x4A, 0x4E, 0x84, 0x21, 0x00]);
+        data.extend_from_slice(&crc32(b"abcabcabcabc").to_le_bytes());
+        data.extend_from_slice(&12u32.to_le_bytes());
+        assert_eq!(extract_rom(data, None).unwrap(), b"abcabcabcabc".to_vec());
+    }
+
+    #[test]
+    fn test_7z_is_rejected() {
+        let data = b"7z\xBC\xAF\x27\x1C\x00\x04".to_vec();
+        assert!(matches!(
+            extract_rom(data, None),
+            Err(LoadError::Archive(_))
+        ));
+    }
+}
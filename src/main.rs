@@ -1,71 +1,354 @@
-use crate::cartridge::MBC3;
+use crate::boot::BootRom;
+use crate::cartridge::{BatterySave, CameraSource, CartridgeInfo, GbxFooter, Mapper};
+use crate::colour_scheme::ColourScheme;
 use crate::cpu::CPU;
-use crate::gpu::VRAM;
+use crate::gameboy::Gameboy;
+use crate::gpu::{Renderer, VRAM};
 use crate::mmu::MMU;
+use crate::model::Model;
+use crate::recorder::Recorder;
 
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
 
+mod boot;
 mod cartridge;
+mod colour_scheme;
 mod cpu;
 mod cpu_comprehensive_tests;
 mod gameboy;
 mod gpu;
+mod image;
+mod info;
+mod loader;
 mod mmu;
+mod model;
 mod opcodes;
+mod patch;
+mod recorder;
 mod registers;
 mod run_loop;
 mod sprite;
+mod timer;
 mod utility {
+    pub(crate) mod checksum;
     pub(crate) mod convenience;
+    pub(crate) mod file;
+    pub(crate) mod inflate;
+    pub(crate) mod png;
+    pub(crate) mod quit;
     pub mod ui_state;
 }
 pub mod interrupts;
 use std::sync::mpsc;
 use utility::ui_state::UIState;
 
+// what a headless run saves once it gets to its frame
+enum Capture {
+    Screenshot,
+    DebugViews,
+}
+
 fn main() {
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("info") {
+        args.next();
+        // scripts checking a batch of ROMs go by the exit status
+        if let Err(error) = info(args) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+        return;
+    }
+
     println!("Hello, world!");
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    let mut file_path = None;
+    let mut entry = None;
+    let mut patch = None;
+    let mut mapper = None;
+    let mut boot_rom = None;
+    let mut model = None;
+    let mut renderer = None;
+    let mut colour_scheme = None;
+    let mut lenient_access = false;
+    let mut record = None;
+    let mut camera = None;
+    let mut captures = Vec::new();
+    let mut scale = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // which file to use when a zip holds more than one ROM
+            "--entry" => entry = args.next(),
+            "--patch" => patch = args.next(),
+            // unlicensed carts lie in their headers, so this wins over everything; bootleg
+            // boards such as bootleg-mbc1:7000 can only be picked this way
+            "--mapper" => mapper = args.next(),
+            "--boot-rom" => boot_rom = args.next(),
+            "--model" => model = args.next(),
+            "--renderer" => renderer = args.next(),
+            // dmg, pocket, light or a file with four RGB colours
+            "--palette" => colour_scheme = args.next(),
+            "--lenient-access" => lenient_access = true,
+            // a .y4m or .gif file
+            "--record" => record = args.next(),
+            // a 128x112 binary PGM the Pocket Camera sees instead of the test pattern
+            "--camera" => camera = args.next().map(|path| (path, false)),
+            // same, but read again on every capture so another program can update it
+            "--camera-live" => camera = args.next().map(|path| (path, true)),
+            // run without a window for that many frames, save a PNG or the tile sheet,
+            // maps and OAM into a directory, and quit
+            "--screenshot-after" => captures.extend(
+                args.next()
+                    .zip(args.next())
+                    .map(|(frames, path)| (frames, path, Capture::Screenshot)),
+            ),
+            "--debug-views-after" => captures.extend(
+                args.next()
+                    .zip(args.next())
+                    .map(|(frames, directory)| (frames, directory, Capture::DebugViews)),
+            ),
+            "--scale" => scale = args.next(),
+            _ => file_path = Some(arg),
+        }
+    }
+
+    let Some(file_path) = file_path else {
         eprintln!("Provide file path");
         return;
-    }
+    };
 
-    let file_path = &args[1];
+    let mut timed_captures = Vec::new();
+    for (frames, path, capture) in captures {
+        match frames.parse::<u64>() {
+            Ok(frames) => timed_captures.push((frames, path, capture)),
+            Err(_) => {
+                eprintln!("Invalid frame count {}", frames);
+                return;
+            }
+        }
+    }
+    timed_captures.sort_by_key(|(frames, _, _)| *frames);
+    let scale = match scale.map(|scale| scale.parse::<usize>()) {
+        Some(Ok(scale)) if scale > 0 => scale,
+        Some(_) => {
+            eprintln!("Scale needs to be a whole number from 1 up");
+            return;
+        }
+        None => 1,
+    };
 
-    let mut file = match File::open(file_path) {
-        Ok(file) => file,
+    let mut buffer = match loader::load_rom(Path::new(&file_path), entry.as_deref()) {
+        Ok(buffer) => buffer,
         Err(error) => {
-            eprintln!("Error opening file: {}", error);
+            eprintln!("Error loading {}: {}", file_path, error);
             return;
         }
     };
 
-    let mut buffer = Vec::new();
-    if let Err(error) = file.read_to_end(&mut buffer) {
-        eprintln!("Error reading file: {}", error);
-        return;
+    let gbx = GbxFooter::split(&mut buffer);
+
+    // patches go on before anything looks at the header, they can change the mapper
+    let patch_path = patch
+        .map(PathBuf::from)
+        .or_else(|| patch::sidecar(Path::new(&file_path)));
+    if let Some(patch_path) = patch_path {
+        buffer = match patch::apply_file(&buffer, &patch_path) {
+            Ok(patched) => patched,
+            Err(error) => {
+                eprintln!("Error applying {}: {}", patch_path.display(), error);
+                return;
+            }
+        };
+    }
+
+    let mut info = match CartridgeInfo::from_header(&buffer) {
+        Ok(info) => info,
+        Err(error) => {
+            eprintln!("Error reading {}: {}", file_path, error);
+            return;
+        }
+    };
+    if let Some(gbx) = &gbx {
+        info.apply_gbx(gbx);
     }
+    if let Some(name) = mapper {
+        match Mapper::from_name(&name) {
+            Some(mapper) => info.mapper = mapper,
+            None => {
+                eprintln!("Unknown mapper {}", name);
+                return;
+            }
+        }
+    } else if gbx.is_none() {
+        if let Some(mapper) = cartridge::detect_unlicensed(&buffer) {
+            info.mapper = mapper;
+        }
+    }
+    let mut mbc = cartridge::from_info(&info, &buffer);
+
+    let camera_source = match camera {
+        Some((camera, live)) => {
+            let source = if live {
+                CameraSource::from_pgm_live(Path::new(&camera))
+            } else {
+                CameraSource::from_pgm(Path::new(&camera))
+            };
+            match source {
+                Ok(source) => Some(source),
+                Err(error) => {
+                    eprintln!("Error loading camera picture {}: {}", camera, error);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let battery_save = if info.has_battery {
+        let battery_save = BatterySave::for_rom(Path::new(&file_path));
+        // carrying on would overwrite the save we couldn't read with blank RAM
+        if let Err(error) = battery_save.load(&mut mbc) {
+            eprintln!(
+                "Error reading save file {}: {}",
+                battery_save.path().display(),
+                error
+            );
+            return;
+        }
+        Some(battery_save)
+    } else {
+        None
+    };
+
+    let boot_rom = match boot_rom.map(|path| BootRom::from_file(Path::new(&path))) {
+        Some(Ok(boot_rom)) => Some(boot_rom),
+        Some(Err(error)) => {
+            eprintln!("Error loading boot ROM: {}", error);
+            return;
+        }
+        None => None,
+    };
+    // an explicit model wins, then whatever the boot ROM was dumped from, then the cart
+    let model = match model {
+        Some(name) => match Model::from_name(&name) {
+            Some(model) => model,
+            None => {
+                eprintln!("Unknown model {}", name);
+                return;
+            }
+        },
+        None => match &boot_rom {
+            Some(boot_rom) if boot_rom.is_cgb() => Model::CGB,
+            Some(_) => Model::DMG,
+            None => Model::for_cartridge(&buffer),
+        },
+    };
 
-    let mut buffer_offset = 0;
+    let renderer = match renderer {
+        Some(name) => match Renderer::from_name(&name) {
+            Some(renderer) => renderer,
+            None => {
+                eprintln!("Unknown renderer {}", name);
+                return;
+            }
+        },
+        None => Renderer::Scanline,
+    };
 
-    let rom_banks = buffer
-        .chunks(0x4000)
-        .map(|c| {
-            let mut sized_array = [0u8; 0x4000];
-            sized_array.copy_from_slice(c);
-            Box::new(sized_array)
-        })
-        .collect::<Vec<_>>()
-        .into_boxed_slice();
+    let colour_scheme = match colour_scheme {
+        Some(name) => match ColourScheme::from_name(&name) {
+            Some(colour_scheme) => colour_scheme,
+            None => match ColourScheme::from_file(Path::new(&name)) {
+                Ok(colour_scheme) => colour_scheme,
+                Err(error) => {
+                    eprintln!("Error loading palette {}: {}", name, error);
+                    return;
+                }
+            },
+        },
+        None => ColourScheme::default(),
+    };
 
-    let mbc3 = MBC3::new(rom_banks);
     let mut gpu = VRAM::new();
-    let mut mmu = MMU::new(&mut gpu, mbc3);
-    let cpu = CPU::new(&mut mmu);
+    gpu.set_renderer(renderer);
+    let mut mmu = MMU::new(&mut gpu, mbc);
+    mmu.set_model(model);
+    mmu.set_lenient_access(lenient_access);
+    let booting = boot_rom.is_some();
+    if let Some(boot_rom) = boot_rom {
+        mmu.set_boot_rom(boot_rom);
+    }
+    let mut cpu = CPU::new(&mut mmu);
+    if booting {
+        cpu.power_on();
+    } else {
+        cpu.skip_boot();
+    }
+    let mut gameboy = Gameboy::new(cpu, battery_save);
+    gameboy.set_colour_scheme(colour_scheme);
+    if let Some(source) = camera_source {
+        gameboy.set_camera_source(source);
+    }
+    if let Some(record) = record {
+        match Recorder::from_path(Path::new(&record)) {
+            Ok(recorder) => gameboy.start_recording(recorder),
+            Err(error) => {
+                eprintln!("Error recording to {}: {}", record, error);
+                return;
+            }
+        }
+    }
+
+    if !timed_captures.is_empty() {
+        let mut frames_run = 0;
+        for (frames, path, capture) in timed_captures {
+            gameboy.run_frames(frames - frames_run);
+            frames_run = frames;
+            let result = match capture {
+                Capture::Screenshot => gameboy
+                    .screenshot()
+                    .scaled(scale)
+                    .save_png(Path::new(&path)),
+                Capture::DebugViews => gameboy.save_debug_views(Path::new(&path)),
+            };
+            if let Err(error) = result {
+                eprintln!("Error saving {}: {}", path, error);
+            }
+        }
+        return;
+    }
+
+    // there's no window yet to send input, so the loop runs until Ctrl-C or SIGTERM
+    let (_tx, rx) = mpsc::channel::<UIState>();
+    utility::quit::install_handler();
+    run_loop::run_loop(gameboy, rx, &utility::quit::QUIT);
+}
 
-    let (tx, rx) = mpsc::channel::<UIState>();
+// `gb info [--json] [--entry NAME] <rom>`, prints the header without running anything
+fn info(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut file_path = None;
+    let mut entry = None;
+    let mut json = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--entry" => entry = args.next(),
+            _ => file_path = Some(arg),
+        }
+    }
+
+    let file_path = file_path.ok_or("Provide file path")?;
+    let buffer = loader::load_rom(Path::new(&file_path), entry.as_deref())
+        .map_err(|error| format!("Error loading {}: {}", file_path, error))?;
+    // the header length is only known once a GBX footer is off, from_rom checks it
+    let info = info::RomInfo::from_rom(&buffer)
+        .map_err(|error| format!("Error reading {}: {}", file_path, error))?;
+    if json {
+        print!("{}", info.to_json());
+    } else {
+        print!("{}", info.to_text());
+    }
+    Ok(())
 }
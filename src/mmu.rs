@@ -1,7 +1,10 @@
-use crate::cartridge::{MBC, MBC3};
-use crate::gpu::VRAM;
+use crate::boot::{self, BootRom};
+use crate::cartridge::{WritableMemory, MBC, MBC3};
+use crate::cpu::Interrupt;
+use crate::gpu::{Diagnostic, GpuEvent, VRAM};
 use crate::interrupts::Interrupts;
-use crate::sprite::Sprite;
+use crate::model::Model;
+use crate::timer::Timer;
 
 pub struct MMU<'a, T>
 where
@@ -10,34 +13,127 @@ where
     vram: &'a mut VRAM,
     mbc: T,
     working_memory: Box<[u8; 0x2000]>,
-    oam: Box<[Sprite; 40]>,
+    high_ram: Box<[u8; 0x7F]>,
+    // registers without hardware behind them yet just hold what was written
+    io: Box<[u8; 0x80]>,
+    boot_rom: Option<BootRom>,
+    model: Model,
+    timer: Timer,
     interrupts: Interrupts,
+    // lets the CPU into VRAM and OAM while the PPU is using them
+    lenient_access: bool,
 }
 
 impl<'a, T: MBC> MMU<'a, T> {
     pub fn new(vram: &'a mut VRAM, mbc: T) -> MMU<T> {
-        let mut sprites: Box<[Sprite; 40]> = Box::new([(); 40].map(|_| Sprite::new()));
-
         MMU {
             vram,
             mbc,
             working_memory: Box::new([0u8; 0x2000]),
-            oam: sprites,
+            high_ram: Box::new([0u8; 0x7F]),
+            io: Box::new([0u8; 0x80]),
+            boot_rom: None,
+            model: Model::DMG,
+            timer: Timer::new(),
             interrupts: Interrupts::new(),
+            lenient_access: false,
         }
     }
 
     pub fn new_with_mbc3(vram: &'a mut VRAM) -> MMU<MBC3> {
         let rom_banks = Box::new([(); 0x80].map(|_| Box::new([0u8; 0x4000])));
-        let mbc3 = MBC3::new(rom_banks);
+        let mut mbc3 = MBC3::new(rom_banks, 0x8000);
+        mbc3.write(0x0000, 0x0A);
 
         MMU::new(vram, mbc3)
     }
 
+    pub fn set_boot_rom(&mut self, boot_rom: BootRom) {
+        self.boot_rom = Some(boot_rom);
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.vram.set_model(model);
+    }
+
+    /// Called with the old value of a register pair the CPU increments or decrements,
+    /// the PPU decides whether that hits the OAM bug.
+    pub fn corrupt_oam(&mut self, address: u16) {
+        if (0xFE00..=0xFEFF).contains(&address) {
+            self.vram.corrupt_oam();
+        }
+    }
+
+    /// Some homebrew only works on emulators that never block VRAM or OAM.
+    pub fn set_lenient_access(&mut self, lenient_access: bool) {
+        self.lenient_access = lenient_access;
+    }
+
+    /// Leaves I/O registers and VRAM the way the boot ROM would have.
+    pub fn skip_boot(&mut self) {
+        let model = self.model;
+        for (address, value) in boot::io_registers(model) {
+            // straight into the registers, a bus write could start a DMA or raise STAT
+            let address = address as usize;
+            match address {
+                0xFF04..=0xFF07 => self.timer.load(address, value),
+                0xFF0F => self.interrupts.set_flags(value),
+                0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.vram.load_register(address, value),
+                _ => self.io[address & 0x7F] = value,
+            }
+        }
+        self.timer.set_divider(boot::divider(model));
+        self.vram.skip_boot();
+
+        let logo: Vec<u8> = (0x0104..0x0134).map(|address| self.read(address)).collect();
+        for (address, value) in boot::vram_contents(model, &logo) {
+            self.vram.write(address as usize, value);
+        }
+    }
+
+    /// Advances everything on the bus that runs off the system clock. The PPU's events
+    /// are handed back, the rest is requested here.
+    pub fn tick(&mut self, ticks: u64) -> Vec<GpuEvent> {
+        self.mbc.tick(ticks);
+        if self.timer.tick(ticks) {
+            self.interrupts.request(Interrupt::Timer);
+        }
+        self.vram.go(ticks)
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        self.vram.take_diagnostics()
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts.request(interrupt);
+    }
+
+    pub fn vram(&self) -> &VRAM {
+        self.vram
+    }
+
+    pub fn mbc(&self) -> &T {
+        &self.mbc
+    }
+
+    pub fn mbc_mut(&mut self) -> &mut T {
+        &mut self.mbc
+    }
+
     pub fn read(&self, address: u16) -> u8 {
         let address = address as usize;
         match address {
-            0x0000..=0x7FFF => self.mbc.read(address),
+            0x0000..=0x7FFF => match &self.boot_rom {
+                Some(boot_rom) if boot_rom.maps(address) => boot_rom.read(address),
+                _ => self.mbc.read(address),
+            },
+            0x8000..=0x9FFF if !self.lenient_access && !self.vram.vram_accessible() => 0xFF,
             0x8000..=0x9FFF => self.vram.read(address),
             0xA000..=0xBFFF => self.mbc.read(address),
             0xC000..=0xDFFF => {
@@ -48,17 +144,11 @@ impl<'a, T: MBC> MMU<'a, T> {
                 // forbidden according to manual but in actuality, it's a echo of working ram
                 panic!("unimplemented read to 0xE000..=0xFDFF")
             }
-            0xFE00..=0xFE9F => {
-                // TODO: implement OAM access here
-                panic!("unimplemented OAM read");
-            }
-            0xFEA0..=0xFEFF => {
-                panic!("unimplemented");
-            }
+            0xFE00..=0xFE9F if !self.lenient_access && !self.vram.oam_accessible() => 0xFF,
+            0xFE00..=0xFE9F => self.vram.read_oam(address & 0xFF),
+            0xFEA0..=0xFEFF => self.model.unusable_memory_read(address as u16),
             0xFF00..=0xFF7F => self.read_register(address),
-            0xFF80..=0xFFFE => {
-                panic!("unimplemented");
-            }
+            0xFF80..=0xFFFE => self.high_ram[address & 0x7F],
             0xFFFF => self.interrupts.get(),
             _ => panic!("unimplemented address space"),
         }
@@ -68,6 +158,7 @@ impl<'a, T: MBC> MMU<'a, T> {
         let address = address as usize;
         match address {
             0x0000..=0x7FFF => self.mbc.write(address, value),
+            0x8000..=0x9FFF if !self.lenient_access && !self.vram.vram_accessible() => {}
             0x8000..=0x9FFF => self.vram.write(address, value),
             0xA000..=0xBFFF => self.mbc.write(address, value),
             0xC000..=0xDFFF => {
@@ -78,18 +169,14 @@ impl<'a, T: MBC> MMU<'a, T> {
                 // forbidden according to manual but in actuality, it's a echo of working ram
                 panic!("unimplemented write to 0xE000..=0xFDFF")
             }
-            0xFE00..=0xFE9F => {
-                // TODO: implement OAM access here
-                panic!("unimplemented OAM write");
-            }
-            0xFEA0..=0xFEFF => {
-                panic!("unimplemented");
-            }
+            0xFE00..=0xFE9F if !self.lenient_access && !self.vram.oam_accessible() => {}
+            0xFE00..=0xFE9F => self.vram.write_oam(address & 0xFF, value),
+            0xFEA0..=0xFEFF => {} // nothing is wired up there
             0xFF00..=0xFF7F => {
                 self.write_register(address, value);
             }
             0xFF80..=0xFFFE => {
-                panic!("unimplemented");
+                self.high_ram[address & 0x7F] = value;
             }
             0xFFFF => {
                 self.interrupts.set(value);
@@ -103,19 +190,29 @@ impl<'a, T: MBC> MMU<'a, T> {
             0xFF00 => {} // TODO: P1
             0xFF01 => {} // TODO: SB
             0xFF02 => {} // TODO: SC
-            0xFF04 => {} // TODO: DIV
-            0xFF05 => {} // TODO: TIMA
-            0xFF06 => {} // TODO: TMA
-            0xFF07 => {} // TODO: TAC
+            0xFF04..=0xFF07 => self.timer.write(address, value),
+            0xFF0F => self.interrupts.set_flags(value),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => {
+                // only the STAT line can be raised by a write
+                if !self.vram.write_register(address, value).is_empty() {
+                    self.interrupts.request(Interrupt::LCD);
+                }
+            }
             0xFF4D => {} // TODO: KEY1
             0xFF46 => {
                 // DMA
                 self.dma(value);
             }
             0xFF4F => {} // TODO: VBK
+            0xFF50 => {
+                // the boot ROM's last act, there's no mapping it back in
+                if value != 0 {
+                    self.boot_rom = None;
+                }
+            }
             0xFF56 => {} // TODO: RP
             0xFF70 => {} // TODO: SVBK
-            _ => panic!("unimplemented!"),
+            _ => self.io[address & 0x7F] = value,
         }
     }
 
@@ -124,15 +221,15 @@ impl<'a, T: MBC> MMU<'a, T> {
             0xFF00 => 0u8, // TODO: P1
             0xFF01 => 0u8, // TODO: SB
             0xFF02 => 0u8, // TODO: SC
-            0xFF04 => 0u8, // TODO: DIV
-            0xFF05 => 0u8, // TODO: TIMA
-            0xFF06 => 0u8, // TODO: TMA
-            0xFF07 => 0u8, // TODO: TAC
+            0xFF04..=0xFF07 => self.timer.read(address),
+            0xFF0F => self.interrupts.get_flags(),
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.vram.read_register(address),
             0xFF4D => 0u8, // TODO: KEY1
             0xFF56 => 0u8, // TODO: RP
             0xFF4F => 0u8, // TODO: VBK
+            0xFF50 => 0xFF,
             0xFF70 => 0u8, // TODO: SVBK
-            _ => panic!("unimplemented!"),
+            _ => self.io[address & 0x7F],
         }
     }
 
@@ -140,18 +237,51 @@ impl<'a, T: MBC> MMU<'a, T> {
         // a lazy OAM, this is done instantaneously in terms of machine ticks
         // a proper implementation will be performed asynchronously with regular code
         let start_address = (value as u16) << 8;
-        for i in (0..40).step_by(4) {
-            let sprite_address = start_address + i;
-            let y_pos = self.read(sprite_address);
-            let x_pos = self.read(sprite_address + 1);
-            let tile_number = self.read(sprite_address + 2);
-            let flags = self.read(sprite_address + 3);
-            let i = i as usize;
-            let sprite = &mut self.oam[i];
-            sprite.y = y_pos;
-            sprite.x = x_pos;
-            sprite.tile_number = tile_number;
-            sprite.write_flags(flags);
+        for i in 0..0xA0 {
+            let value = self.read(start_address + i);
+            self.vram.write_oam(i as usize, value);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vram_and_oam_blocked_while_drawing() {
+        let mut vram = VRAM::new();
+        let mut mmu = MMU::<MBC3>::new_with_mbc3(&mut vram);
+        mmu.write(0x8000, 0x12);
+        mmu.write(0xFE00, 0x34);
+        mmu.write(0xFF40, 0x80);
+
+        // OAM scan
+        assert_eq!(mmu.read(0x8000), 0x12);
+        assert_eq!(mmu.read(0xFE00), 0xFF);
+        mmu.write(0xFE00, 0x56);
+
+        mmu.tick(80);
+        assert_eq!(mmu.read(0x8000), 0xFF);
+        mmu.write(0x8000, 0x78);
+
+        mmu.tick(172);
+        assert_eq!(mmu.read(0x8000), 0x12);
+        assert_eq!(mmu.read(0xFE00), 0x34);
+
+        mmu.tick(456 - 252 + 80);
+        mmu.set_lenient_access(true);
+        mmu.write(0x8000, 0x78);
+        assert_eq!(mmu.read(0x8000), 0x78);
+    }
+
+    #[test]
+    fn test_skip_boot_leaves_only_vblank_requested() {
+        let mut vram = VRAM::new();
+        let mut mmu = MMU::<MBC3>::new_with_mbc3(&mut vram);
+        mmu.skip_boot();
+        assert_eq!(mmu.read(0xFF0F), 0xE1);
+        assert_eq!(mmu.read(0xFF40), 0x91);
+        assert_eq!(mmu.read(0xFF46), 0xFF);
+    }
+}
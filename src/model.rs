@@ -0,0 +1,87 @@
+/// The console being emulated. DMG0 is the first DMG boot ROM revision, which only
+/// matters for the state a skipped boot leaves behind.
+///
+/// The MMU hands it on to the PPU, and the CPU asks the MMU. The timer behaves the same
+/// on every model as far as it's emulated here, and there's no APU yet to give the wave
+/// RAM quirks to, so neither of them takes one.
+// named after the consoles' part numbers, the same way MBC1 and VRAM are
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    DMG0,
+    DMG,
+    MGB,
+    SGB,
+    SGB2,
+    CGB,
+    AGB,
+}
+
+impl Model {
+    /// Parses the names accepted by `--model`.
+    pub fn from_name(name: &str) -> Option<Model> {
+        let model = match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Model::DMG0,
+            "dmg" => Model::DMG,
+            "mgb" => Model::MGB,
+            "sgb" => Model::SGB,
+            "sgb2" => Model::SGB2,
+            "cgb" => Model::CGB,
+            "agb" => Model::AGB,
+            _ => return None,
+        };
+        Some(model)
+    }
+
+    /// The console a cartridge is best run on when nobody asked for one, going by the
+    /// CGB flag at 0x0143.
+    pub fn for_cartridge(rom: &[u8]) -> Model {
+        match rom.get(0x0143) {
+            Some(flag) if flag & 0x80 != 0 => Model::CGB,
+            _ => Model::DMG,
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+
+    /// What reading the unusable area at 0xFEA0..=0xFEFF gives back.
+    pub fn unusable_memory_read(&self, address: u16) -> u8 {
+        if self.is_cgb() {
+            // later CGB revisions echo the address' second nibble in both halves
+            let nibble = (address & 0x00F0) as u8;
+            nibble | (nibble >> 4)
+        } else {
+            0x00
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_cartridge() {
+        let mut rom = vec![0u8; 0x8000];
+        assert_eq!(Model::for_cartridge(&rom), Model::DMG);
+        rom[0x0143] = 0x80;
+        assert_eq!(Model::for_cartridge(&rom), Model::CGB);
+        rom[0x0143] = 0xC0;
+        assert_eq!(Model::for_cartridge(&rom), Model::CGB);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Model::from_name("SGB2"), Some(Model::SGB2));
+        assert_eq!(Model::from_name("gba"), None);
+    }
+
+    #[test]
+    fn test_unusable_memory_read() {
+        assert_eq!(Model::DMG.unusable_memory_read(0xFEB7), 0x00);
+        assert_eq!(Model::CGB.unusable_memory_read(0xFEB7), 0xBB);
+        assert_eq!(Model::AGB.unusable_memory_read(0xFEA0), 0xAA);
+    }
+}
@@ -0,0 +1,467 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::utility::checksum::crc32;
+
+const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+// the biggest cartridge ever made is 8MB, anything claiming more is damaged or hostile
+const MAX_TARGET_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum PatchError {
+    Io(io::Error),
+    /// The patch is damaged or isn't in a format we know.
+    Format(String),
+    /// A UPS/BPS checksum didn't match, `which` is "source", "target" or "patch".
+    Checksum {
+        which: &'static str,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatchError::Io(error) => write!(f, "{}", error),
+            PatchError::Format(message) => write!(f, "{}", message),
+            PatchError::Checksum {
+                which,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} CRC32 mismatch, expected {:08X} but got {:08X}",
+                which, expected, actual
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for PatchError {
+    fn from(error: io::Error) -> PatchError {
+        PatchError::Io(error)
+    }
+}
+
+/// A patch sitting next to the ROM with the same name, `game.gb` picks up `game.ips`.
+pub fn sidecar(rom_path: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+pub fn apply_file(rom: &[u8], patch_path: &Path) -> Result<Vec<u8>, PatchError> {
+    apply(rom, &fs::read(patch_path)?)
+}
+
+/// Applies an IPS, UPS or BPS patch, telling them apart by their magic bytes.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::Format(
+            "not an IPS, UPS or BPS patch".to_string(),
+        ))
+    }
+}
+
+fn truncated() -> PatchError {
+    PatchError::Format("patch is truncated".to_string())
+}
+
+fn overflow() -> PatchError {
+    PatchError::Format("patch number overflows".to_string())
+}
+
+fn bytes<'a>(patch: &'a [u8], offset: &mut usize, length: usize) -> Result<&'a [u8], PatchError> {
+    let end = offset.checked_add(length).ok_or_else(truncated)?;
+    let slice = patch.get(*offset..end).ok_or_else(truncated)?;
+    *offset = end;
+    Ok(slice)
+}
+
+fn target_size(patch: &[u8], offset: &mut usize) -> Result<usize, PatchError> {
+    let target_size = varint(patch, offset)?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::Format(format!(
+            "patch output would be {} bytes, more than any cartridge",
+            target_size
+        )));
+    }
+    Ok(target_size)
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut output = rom.to_vec();
+    let mut offset = 5;
+
+    loop {
+        let record = bytes(patch, &mut offset, 3)?;
+        if record == b"EOF" {
+            break;
+        }
+        let address = (record[0] as usize) << 16 | (record[1] as usize) << 8 | record[2] as usize;
+        let size = bytes(patch, &mut offset, 2)?;
+        let size = (size[0] as usize) << 8 | size[1] as usize;
+
+        // a zero size means a run of a single byte
+        let data = if size == 0 {
+            let run = bytes(patch, &mut offset, 3)?;
+            vec![run[2]; (run[0] as usize) << 8 | run[1] as usize]
+        } else {
+            bytes(patch, &mut offset, size)?.to_vec()
+        };
+
+        if output.len() < address + data.len() {
+            output.resize(address + data.len(), 0);
+        }
+        output[address..address + data.len()].copy_from_slice(&data);
+    }
+
+    // some patchers append the size to truncate the output to
+    if let Ok(length) = bytes(patch, &mut offset, 3) {
+        output
+            .truncate((length[0] as usize) << 16 | (length[1] as usize) << 8 | length[2] as usize);
+    }
+    Ok(output)
+}
+
+// the variable length integers UPS and BPS share
+fn varint(patch: &[u8], offset: &mut usize) -> Result<usize, PatchError> {
+    let mut value = 0usize;
+    let mut shift = 1usize;
+    loop {
+        let byte = *patch.get(*offset).ok_or_else(truncated)?;
+        *offset += 1;
+        value = ((byte & 0x7F) as usize)
+            .checked_mul(shift)
+            .and_then(|digit| value.checked_add(digit))
+            .ok_or_else(overflow)?;
+        if byte & 0x80 == 0x80 {
+            return Ok(value);
+        }
+        shift = shift.checked_mul(0x80).ok_or_else(overflow)?;
+        value = value.checked_add(shift).ok_or_else(overflow)?;
+    }
+}
+
+// the three CRC32s at the end of UPS and BPS patches
+fn footer(patch: &[u8]) -> Result<(u32, u32), PatchError> {
+    if patch.len() < 12 {
+        return Err(truncated());
+    }
+    let crc_at = |offset: usize| {
+        u32::from_le_bytes([
+            patch[offset],
+            patch[offset + 1],
+            patch[offset + 2],
+            patch[offset + 3],
+        ])
+    };
+    let end = patch.len() - 12;
+    check("patch", crc_at(end + 8), crc32(&patch[..end + 8]))?;
+    Ok((crc_at(end), crc_at(end + 4)))
+}
+
+fn check(which: &'static str, expected: u32, actual: u32) -> Result<(), PatchError> {
+    if expected != actual {
+        return Err(PatchError::Checksum {
+            which,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (source_crc, target_crc) = footer(patch)?;
+    check("source", source_crc, crc32(rom))?;
+
+    let mut offset = 4;
+    let _source_size = varint(patch, &mut offset)?;
+    let target_size = target_size(patch, &mut offset)?;
+    let mut output = rom.to_vec();
+    output.resize(target_size, 0);
+
+    // hunks skip ahead then XOR bytes in until a zero
+    let end = patch.len() - 12;
+    let mut position = 0usize;
+    while offset < end {
+        position = position
+            .checked_add(varint(patch, &mut offset)?)
+            .ok_or_else(overflow)?;
+        loop {
+            let byte = *patch[..end].get(offset).ok_or_else(truncated)?;
+            offset += 1;
+            // past the end of the output nothing gets written, so saturating is harmless
+            position = position.saturating_add(1);
+            if byte == 0 {
+                break;
+            }
+            if let Some(target) = output.get_mut(position - 1) {
+                *target ^= byte;
+            }
+        }
+    }
+
+    check("target", target_crc, crc32(&output))?;
+    Ok(output)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (source_crc, target_crc) = footer(patch)?;
+    check("source", source_crc, crc32(rom))?;
+
+    let mut offset = 4;
+    let _source_size = varint(patch, &mut offset)?;
+    let target_size = target_size(patch, &mut offset)?;
+    let metadata_size = varint(patch, &mut offset)?;
+    offset = offset.checked_add(metadata_size).ok_or_else(truncated)?;
+
+    let end = patch.len() - 12;
+    let actions = &patch[..end];
+    let mut output = Vec::with_capacity(target_size);
+    let mut source_relative = 0isize;
+    let mut target_relative = 0isize;
+    let out_of_range = || PatchError::Format("patch copies from outside the ROM".to_string());
+
+    while offset < end {
+        let data = varint(actions, &mut offset)?;
+        let length = (data >> 2) + 1;
+        if length > target_size - output.len() {
+            return Err(PatchError::Format(
+                "patch writes past its target size".to_string(),
+            ));
+        }
+        match data & 0x03 {
+            // SourceRead
+            0 => {
+                let start = output.len();
+                let source = rom.get(start..start + length).ok_or_else(out_of_range)?;
+                output.extend_from_slice(source);
+            }
+            // TargetRead
+            1 => output.extend_from_slice(bytes(actions, &mut offset, length)?),
+            // SourceCopy
+            2 => {
+                let data = varint(actions, &mut offset)?;
+                let delta = (data >> 1) as isize;
+                source_relative = if data & 0x01 == 0x01 {
+                    source_relative.checked_sub(delta)
+                } else {
+                    source_relative.checked_add(delta)
+                }
+                .ok_or_else(out_of_range)?;
+                let start = usize::try_from(source_relative).map_err(|_| out_of_range())?;
+                let end = start.checked_add(length).ok_or_else(out_of_range)?;
+                let source = rom.get(start..end).ok_or_else(out_of_range)?;
+                output.extend_from_slice(source);
+                source_relative = end as isize;
+            }
+            // TargetCopy, byte at a time since it can overlap what it's writing
+            _ => {
+                let data = varint(actions, &mut offset)?;
+                let delta = (data >> 1) as isize;
+                target_relative = if data & 0x01 == 0x01 {
+                    target_relative.checked_sub(delta)
+                } else {
+                    target_relative.checked_add(delta)
+                }
+                .ok_or_else(out_of_range)?;
+                for _ in 0..length {
+                    let start = usize::try_from(target_relative).map_err(|_| out_of_range())?;
+                    let byte = *output.get(start).ok_or_else(out_of_range)?;
+                    output.push(byte);
+                    target_relative += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size {
+        return Err(PatchError::Format(
+            "patch output doesn't match its target size".to_string(),
+        ));
+    }
+    check("target", target_crc, crc32(&output))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_varint(mut value: usize, output: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                output.push(0x80 | byte);
+                return;
+            }
+            output.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_varint_overflow() {
+        let mut encoded = vec![0x7F; 12];
+        encoded.push(0xFF);
+        assert!(matches!(
+            varint(&encoded, &mut 0),
+            Err(PatchError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn test_huge_target_rejected() {
+        let rom = [0u8; 0x10];
+        for magic in [&b"UPS1"[..], &b"BPS1"[..]] {
+            let mut patch = magic.to_vec();
+            encode_varint(rom.len(), &mut patch);
+            encode_varint(usize::MAX / 2, &mut patch);
+            encode_varint(0, &mut patch);
+            let patch = with_footer(patch, &rom, &rom);
+            assert!(matches!(apply(&rom, &patch), Err(PatchError::Format(_))));
+        }
+    }
+
+    #[test]
+    fn test_bps_action_past_target_rejected() {
+        let rom = [0u8; 0x10];
+        let mut patch = b"BPS1".to_vec();
+        encode_varint(rom.len(), &mut patch);
+        encode_varint(rom.len(), &mut patch);
+        encode_varint(0, &mut patch);
+        // a SourceCopy as long as the address space
+        encode_varint(usize::MAX & !0x03 | 0x02, &mut patch);
+        encode_varint(0, &mut patch);
+        let patch = with_footer(patch, &rom, &rom);
+        assert!(matches!(apply(&rom, &patch), Err(PatchError::Format(_))));
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 16511, 16512, 0x123456] {
+            let mut encoded = Vec::new();
+            encode_varint(value, &mut encoded);
+            assert_eq!(varint(&encoded, &mut 0).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_ips_records_and_rle() {
+        let rom = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+        let output = apply(&rom, &patch).unwrap();
+        assert_eq!(
+            output,
+            [0x00, 0xAA, 0xBB, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC, 0xCC]
+        );
+    }
+
+    #[test]
+    fn test_ips_truncate() {
+        let mut patch = b"PATCHEOF".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02]);
+        assert_eq!(apply(&[1, 2, 3, 4], &patch).unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn test_ups() {
+        let source = [0x10, 0x20, 0x30, 0x40];
+        let target = [0x10, 0x21, 0x30, 0x40, 0x50];
+        let mut patch = b"UPS1".to_vec();
+        encode_varint(source.len(), &mut patch);
+        encode_varint(target.len(), &mut patch);
+        encode_varint(1, &mut patch);
+        patch.extend_from_slice(&[0x20 ^ 0x21, 0x00]);
+        // the terminating zero already stepped over 0x30
+        encode_varint(1, &mut patch);
+        patch.extend_from_slice(&[0x50, 0x00]);
+        let patch = with_footer(patch, &source, &target);
+
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_ups_wrong_source() {
+        let source = [0x10, 0x20];
+        let mut patch = b"UPS1".to_vec();
+        encode_varint(2, &mut patch);
+        encode_varint(2, &mut patch);
+        let patch = with_footer(patch, &source, &source);
+
+        match apply(&[0x11, 0x20], &patch) {
+            Err(PatchError::Checksum { which, .. }) => assert_eq!(which, "source"),
+            _ => panic!("expected a source checksum error"),
+        }
+    }
+
+    #[test]
+    fn test_bps_actions() {
+        let source = b"abcdef";
+        let target = b"abXYXYXYdef";
+        let mut patch = b"BPS1".to_vec();
+        encode_varint(source.len(), &mut patch);
+        encode_varint(target.len(), &mut patch);
+        encode_varint(0, &mut patch);
+        // SourceRead "ab"
+        encode_varint((2 - 1) << 2, &mut patch);
+        // TargetRead "XY"
+        encode_varint((2 - 1) << 2 | 1, &mut patch);
+        patch.extend_from_slice(b"XY");
+        // TargetCopy 4 bytes from offset 2, overlapping itself
+        encode_varint((4 - 1) << 2 | 3, &mut patch);
+        encode_varint(2 << 1, &mut patch);
+        // SourceCopy "def" from offset 3
+        encode_varint((3 - 1) << 2 | 2, &mut patch);
+        encode_varint(3 << 1, &mut patch);
+        let patch = with_footer(patch, source, target);
+
+        assert_eq!(apply(source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn test_damaged_patch_checksum() {
+        let source = b"abc";
+        let mut patch = b"BPS1".to_vec();
+        encode_varint(3, &mut patch);
+        encode_varint(3, &mut patch);
+        encode_varint(0, &mut patch);
+        encode_varint((3 - 1) << 2, &mut patch);
+        let mut patch = with_footer(patch, source, source);
+        patch[4] ^= 0x01;
+
+        match apply(source, &patch) {
+            Err(PatchError::Checksum { which, .. }) => assert_eq!(which, "patch"),
+            _ => panic!("expected a patch checksum error"),
+        }
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        assert!(matches!(apply(&[], b"nope"), Err(PatchError::Format(_))));
+    }
+}
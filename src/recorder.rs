@@ -0,0 +1,474 @@
+use crate::image::Image;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+// the LCD refreshes every 70224 dots of the 4MiHz clock, just under 60 times a second
+const CLOCK_RATE: u64 = 4194304;
+const DOTS_PER_FRAME: u64 = 70224;
+// most GIF viewers slow down anything faster than this, in hundredths of a second
+const MIN_GIF_DELAY: u64 = 2;
+
+/// Captures every frame the LCD presents, either as a Y4M stream or as an animated GIF.
+/// Both are kept valid on disk after every frame, so a recording survives the emulator
+/// being killed.
+pub struct Recorder {
+    output: Output,
+}
+
+enum Output {
+    Y4m {
+        writer: BufWriter<File>,
+        header_written: bool,
+    },
+    Gif(GifWriter),
+}
+
+impl Recorder {
+    pub fn y4m(path: &Path) -> io::Result<Recorder> {
+        Ok(Recorder {
+            output: Output::Y4m {
+                writer: BufWriter::new(File::create(path)?),
+                header_written: false,
+            },
+        })
+    }
+
+    pub fn gif(path: &Path) -> io::Result<Recorder> {
+        Ok(Recorder {
+            output: Output::Gif(GifWriter::new(BufWriter::new(File::create(path)?))),
+        })
+    }
+
+    /// Picks the format from the extension, `.y4m` or `.gif`.
+    pub fn from_path(path: &Path) -> io::Result<Recorder> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("y4m") => Recorder::y4m(path),
+            Some("gif") => Recorder::gif(path),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "recordings need to end in .y4m or .gif",
+            )),
+        }
+    }
+
+    pub fn add_frame(&mut self, image: &Image) -> io::Result<()> {
+        match &mut self.output {
+            Output::Y4m {
+                writer,
+                header_written,
+            } => {
+                if !*header_written {
+                    writeln!(
+                        writer,
+                        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                        image.width(),
+                        image.height(),
+                        CLOCK_RATE,
+                        DOTS_PER_FRAME
+                    )?;
+                    *header_written = true;
+                }
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&yuv444(image))?;
+                writer.flush()
+            }
+            Output::Gif(gif) => gif.add_frame(image),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.output {
+            Output::Y4m { mut writer, .. } => writer.flush(),
+            Output::Gif(gif) => gif.finish(),
+        }
+    }
+}
+
+// BT.601 with studio swing, the planes one after another at full resolution
+fn yuv444(image: &Image) -> Vec<u8> {
+    let pixels = image.pixels();
+    let count = pixels.len() / 3;
+    let mut planes = vec![0u8; count * 3];
+    for (i, pixel) in pixels.chunks(3).enumerate() {
+        let (r, g, b) = (pixel[0] as i32, pixel[1] as i32, pixel[2] as i32);
+        planes[i] = (16 + ((66 * r + 129 * g + 25 * b + 128) >> 8)) as u8;
+        planes[count + i] = (128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8)) as u8;
+        planes[count * 2 + i] = (128 + ((112 * r - 94 * g - 18 * b + 128) >> 8)) as u8;
+    }
+    planes
+}
+
+/// Writes a frame only when the picture changes, holding it for as long as it stayed
+/// on screen. GIF delays are whole hundredths, so they're rounded against the total
+/// time to keep the animation from drifting.
+///
+/// Every frame goes to disk as soon as it appears, followed by the trailer, and its
+/// delay gets patched in once the next one shows up.
+struct GifWriter {
+    writer: BufWriter<File>,
+    // the frame on disk whose delay isn't known yet
+    last: Option<Image>,
+    last_offset: u64,
+    end_offset: u64,
+    frames: u64,
+    written: u64,
+}
+
+// the delay sits after the extension introducer, label, size and flags
+const GIF_DELAY_OFFSET: u64 = 4;
+
+impl GifWriter {
+    fn new(writer: BufWriter<File>) -> GifWriter {
+        GifWriter {
+            writer,
+            last: None,
+            last_offset: 0,
+            end_offset: 0,
+            frames: 0,
+            written: 0,
+        }
+    }
+
+    fn centiseconds(frames: u64) -> u64 {
+        (frames * DOTS_PER_FRAME * 100 + CLOCK_RATE / 2) / CLOCK_RATE
+    }
+
+    fn add_frame(&mut self, image: &Image) -> io::Result<()> {
+        let now = self.frames;
+        self.frames += 1;
+        match &self.last {
+            Some(last) if last == image => return Ok(()),
+            Some(_) => {
+                let delay = GifWriter::centiseconds(now) - self.written;
+                // anything shorter than a viewer would show gets written over
+                if delay >= MIN_GIF_DELAY {
+                    self.set_delay(delay)?;
+                    self.written += delay;
+                    self.last_offset = self.end_offset;
+                }
+            }
+            None => {
+                let header = gif_header(image);
+                self.writer.write_all(&header)?;
+                self.last_offset = header.len() as u64;
+            }
+        }
+
+        let frame = gif_frame(image, MIN_GIF_DELAY as u16)?;
+        self.writer.seek(SeekFrom::Start(self.last_offset))?;
+        self.writer.write_all(&frame)?;
+        self.writer.write_all(&[0x3B])?;
+        self.end_offset = self.last_offset + frame.len() as u64;
+        self.writer.flush()?;
+        // a frame that got written over may have been longer
+        self.writer.get_ref().set_len(self.end_offset + 1)?;
+        self.last = Some(image.clone());
+        Ok(())
+    }
+
+    fn set_delay(&mut self, delay: u64) -> io::Result<()> {
+        self.writer
+            .seek(SeekFrom::Start(self.last_offset + GIF_DELAY_OFFSET))?;
+        self.writer.write_all(&(delay as u16).to_le_bytes())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if self.last.is_some() {
+            let delay = GifWriter::centiseconds(self.frames) - self.written;
+            self.set_delay(delay.max(MIN_GIF_DELAY))?;
+        }
+        self.writer.flush()
+    }
+}
+
+fn gif_header(image: &Image) -> Vec<u8> {
+    let mut header = b"GIF89a".to_vec();
+    header.extend_from_slice(&(image.width() as u16).to_le_bytes());
+    header.extend_from_slice(&(image.height() as u16).to_le_bytes());
+    // no global colour table, every frame brings its own
+    header.extend_from_slice(&[0x00, 0x00, 0x00]);
+    // loop forever
+    header.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+    header.extend_from_slice(b"NETSCAPE2.0");
+    header.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+    header
+}
+
+fn gif_frame(image: &Image, delay: u16) -> io::Result<Vec<u8>> {
+    let mut colours: Vec<[u8; 3]> = Vec::new();
+    let mut lookup = HashMap::new();
+    let mut indices = Vec::with_capacity(image.width() * image.height());
+    for pixel in image.pixels().chunks(3) {
+        let rgb = [pixel[0], pixel[1], pixel[2]];
+        let index = *lookup.entry(rgb).or_insert_with(|| {
+            colours.push(rgb);
+            colours.len() - 1
+        });
+        if index > 0xFF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GIF frames can't have more than 256 colours",
+            ));
+        }
+        indices.push(index as u8);
+    }
+    // the table holds a power of two colours, at least 4 for the smallest LZW code size
+    let mut table_bits = 2;
+    while (1 << table_bits) < colours.len() {
+        table_bits += 1;
+    }
+
+    // graphic control extension, keep the previous frame underneath
+    let mut frame = vec![0x21, 0xF9, 0x04, 0x04];
+    frame.extend_from_slice(&delay.to_le_bytes());
+    frame.extend_from_slice(&[0x00, 0x00]);
+
+    frame.extend_from_slice(&[0x2C, 0x00, 0x00, 0x00, 0x00]);
+    frame.extend_from_slice(&(image.width() as u16).to_le_bytes());
+    frame.extend_from_slice(&(image.height() as u16).to_le_bytes());
+    frame.push(0x80 | (table_bits - 1));
+    for index in 0..1usize << table_bits {
+        frame.extend_from_slice(&colours.get(index).copied().unwrap_or([0; 3]));
+    }
+
+    frame.push(table_bits);
+    for block in lzw_encode(&indices, table_bits).chunks(0xFF) {
+        frame.push(block.len() as u8);
+        frame.extend_from_slice(block);
+    }
+    frame.push(0x00);
+    Ok(frame)
+}
+
+// GIF flavoured LZW, variable width codes up to 12 bits packed from the low bit up
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end + 1;
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+
+    let mut writer = BitWriter::new();
+    writer.write(clear, code_size);
+    let mut current: Option<u16> = None;
+    for &index in indices {
+        let Some(prefix) = current else {
+            current = Some(index as u16);
+            continue;
+        };
+        if let Some(&code) = dictionary.get(&(prefix, index)) {
+            current = Some(code);
+            continue;
+        }
+
+        writer.write(prefix, code_size);
+        if next_code < 0x1000 {
+            dictionary.insert((prefix, index), next_code);
+            if next_code == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+            next_code += 1;
+        } else {
+            // the table is full, start over
+            writer.write(clear, code_size);
+            dictionary.clear();
+            code_size = min_code_size + 1;
+            next_code = end + 1;
+        }
+        current = Some(index as u16);
+    }
+    if let Some(prefix) = current {
+        writer.write(prefix, code_size);
+    }
+    writer.write(end, code_size);
+    writer.finish()
+}
+
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            output: Vec::new(),
+            buffer: 0,
+            bits: 0,
+        }
+    }
+
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a straightforward GIF LZW decoder to check the encoder against
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let mut code_size = min_code_size as usize + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..clear).map(|i| vec![i as u8]).collect();
+            table.push(Vec::new());
+            table.push(Vec::new());
+        };
+        reset(&mut table);
+
+        let mut output = Vec::new();
+        let mut previous: Option<Vec<u8>> = None;
+        let mut position = 0;
+        loop {
+            let mut code = 0;
+            for bit in 0..code_size {
+                let byte = data[(position + bit) / 8];
+                code |= (((byte >> ((position + bit) % 8)) & 1) as usize) << bit;
+            }
+            position += code_size;
+
+            if code == clear {
+                reset(&mut table);
+                code_size = min_code_size as usize + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return output;
+            }
+            let entry = match (&previous, table.get(code)) {
+                (_, Some(entry)) => entry.clone(),
+                (Some(previous), None) => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                }
+                (None, None) => panic!("bad first code"),
+            };
+            output.extend_from_slice(&entry);
+            if let Some(previous) = previous {
+                if table.len() < 0x1000 {
+                    let mut new_entry = previous;
+                    new_entry.push(entry[0]);
+                    table.push(new_entry);
+                    if table.len() == 1 << code_size && code_size < 12 {
+                        code_size += 1;
+                    }
+                }
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..20000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8 & 0x03
+            })
+            .collect();
+        assert_eq!(lzw_decode(&lzw_encode(&noise, 2), 2), noise);
+
+        let runs: Vec<u8> = (0..30000).map(|i| (i / 37 % 4) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&runs, 2), 2), runs);
+
+        let wide: Vec<u8> = (0..5000).map(|i| (i * 7 % 256) as u8).collect();
+        assert_eq!(lzw_decode(&lzw_encode(&wide, 8), 8), wide);
+    }
+
+    #[test]
+    fn test_yuv444() {
+        let image = Image::new(2, 1, vec![0, 0, 0, 255, 255, 255]);
+        assert_eq!(yuv444(&image), vec![16, 235, 128, 128, 128, 128]);
+    }
+
+    fn solid(shade: u8) -> Image {
+        Image::new(4, 4, vec![shade; 4 * 4 * 3])
+    }
+
+    #[test]
+    fn test_gif_deduplicates_frames() {
+        let path = std::env::temp_dir().join(format!("gb_recorder_{}.gif", std::process::id()));
+        let mut recorder = Recorder::from_path(&path).unwrap();
+        for _ in 0..60 {
+            recorder.add_frame(&solid(0)).unwrap();
+        }
+        for _ in 0..60 {
+            recorder.add_frame(&solid(0xFF)).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let gif = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(gif.last(), Some(&0x3B));
+
+        let delays: Vec<u16> = gif
+            .windows(4)
+            .enumerate()
+            .filter(|(_, window)| window[..3] == [0x21, 0xF9, 0x04])
+            .map(|(i, _)| u16::from_le_bytes([gif[i + 4], gif[i + 5]]))
+            .collect();
+        // a second of each, just over 100 hundredths since the LCD runs a bit under 60Hz
+        assert_eq!(delays, vec![100, 101]);
+    }
+
+    #[test]
+    fn test_gif_is_complete_while_recording() {
+        let path =
+            std::env::temp_dir().join(format!("gb_recorder_live_{}.gif", std::process::id()));
+        let mut recorder = Recorder::from_path(&path).unwrap();
+        recorder.add_frame(&solid(0)).unwrap();
+        // too short to be shown, so the next frame takes its place
+        recorder.add_frame(&solid(0x80)).unwrap();
+        for _ in 0..30 {
+            recorder.add_frame(&solid(0xFF)).unwrap();
+        }
+
+        let gif = std::fs::read(&path).unwrap();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(gif.last(), Some(&0x3B));
+        let frames = gif
+            .windows(3)
+            .filter(|window| *window == [0x21, 0xF9, 0x04])
+            .count();
+        assert_eq!(frames, 2);
+
+        recorder.finish().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_from_path() {
+        let error = Recorder::from_path(Path::new("out.mp4")).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,15 +1,138 @@
-use std::sync::mpsc::Receiver;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{Receiver, TryRecvError},
+};
 
 use crate::{cartridge::MBC, gameboy::Gameboy, utility::ui_state::UIState};
 
-fn run_loop<T: MBC>(mut gameboy: Gameboy<T>, rx: Receiver<UIState>) {
-    loop {
+// what the cart drives on the host side. There's no window with a motor, LED or
+// speaker to hand these to yet, so changes get logged instead
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HostOutputs {
+    rumble: bool,
+    infrared_led: bool,
+    speaker_tone: Option<u8>,
+}
+
+impl HostOutputs {
+    fn of<T: MBC>(gameboy: &Gameboy<T>) -> HostOutputs {
+        HostOutputs {
+            rumble: gameboy.rumble(),
+            infrared_led: gameboy.infrared_led(),
+            speaker_tone: gameboy.speaker_tone(),
+        }
+    }
+
+    fn changes(&self, previous: &HostOutputs) -> Vec<String> {
+        let on_off = |value: bool| if value { "on" } else { "off" };
+        let mut changes = Vec::new();
+        if self.rumble != previous.rumble {
+            changes.push(format!("Rumble {}", on_off(self.rumble)));
+        }
+        if self.infrared_led != previous.infrared_led {
+            changes.push(format!("Infrared LED {}", on_off(self.infrared_led)));
+        }
+        if self.speaker_tone != previous.speaker_tone {
+            changes.push(match self.speaker_tone {
+                Some(tone) => format!("Speaker tone {:02X}", tone),
+                None => "Speaker off".to_string(),
+            });
+        }
+        changes
+    }
+}
+
+/// Runs until `quit` is set or the front-end drops its end of the channel, then writes
+/// the battery save and finishes the recording before returning.
+pub fn run_loop<T: MBC>(mut gameboy: Gameboy<T>, rx: Receiver<UIState>, quit: &AtomicBool) {
+    let mut outputs = HostOutputs::of(&gameboy);
+    while !quit.load(Ordering::SeqCst) {
         let result = rx.try_recv();
         let mut sent_ui_state: Option<UIState> = None;
-        if let Ok(ui_state) = result {
-            sent_ui_state = Some(ui_state);
+        match result {
+            Ok(ui_state) => sent_ui_state = Some(ui_state),
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
         }
 
         gameboy.go(sent_ui_state);
+
+        let current = HostOutputs::of(&gameboy);
+        if current != outputs {
+            for change in current.changes(&outputs) {
+                eprintln!("{}", change);
+            }
+            outputs = current;
+        }
+    }
+
+    gameboy.flush_battery_save();
+    if let Err(error) = gameboy.stop_recording() {
+        eprintln!("Error finishing recording: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cartridge::{BatterySave, MBC3},
+        cpu::CPU,
+        gpu::VRAM,
+        mmu::MMU,
+    };
+    use std::{env, fs, path::PathBuf, sync::mpsc};
+
+    fn gameboy(save_path: PathBuf) -> Gameboy<'static, MBC3> {
+        let gpu = Box::leak(Box::new(VRAM::new()));
+        let mmu = Box::leak(Box::new(MMU::<MBC3>::new_with_mbc3(gpu)));
+        let mut cpu = CPU::new(mmu);
+        cpu.mmu.write(0xA010, 0x69);
+        Gameboy::new(cpu, Some(BatterySave::new(save_path)))
+    }
+
+    fn save_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("gb-loop-{}-{}.sav", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_host_output_changes() {
+        let quiet = HostOutputs {
+            rumble: false,
+            infrared_led: false,
+            speaker_tone: None,
+        };
+        assert!(quiet.changes(&quiet).is_empty());
+        let busy = HostOutputs {
+            rumble: true,
+            infrared_led: true,
+            speaker_tone: Some(0x1A),
+        };
+        assert_eq!(
+            busy.changes(&quiet),
+            vec!["Rumble on", "Infrared LED on", "Speaker tone 1A"]
+        );
+        assert_eq!(quiet.changes(&busy)[2], "Speaker off");
+    }
+
+    #[test]
+    fn test_quit_flushes_save() {
+        let path = save_path("quit");
+        let (_tx, rx) = mpsc::channel();
+        run_loop(gameboy(path.clone()), rx, &AtomicBool::new(true));
+        assert_eq!(fs::read(&path).unwrap()[0x10], 0x69);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_front_end_going_away_flushes_save() {
+        let path = save_path("disconnect");
+        let (tx, rx) = mpsc::channel();
+        drop(tx);
+        run_loop(gameboy(path.clone()), rx, &AtomicBool::new(false));
+        assert_eq!(fs::read(&path).unwrap()[0x10], 0x69);
+        fs::remove_file(path).unwrap();
     }
 }
@@ -7,6 +7,8 @@ pub struct Sprite {
     pub y_flip_flag: bool,
     pub x_flip_flag: bool,
     pub pallete_number: u8,
+    // CGB bank and palette bits, plain RAM on DMG but they still read back
+    pub cgb_flags: u8,
 }
 
 impl Sprite {
@@ -19,6 +21,7 @@ impl Sprite {
             y_flip_flag: false,
             x_flip_flag: false,
             pallete_number: 0,
+            cgb_flags: 0,
         }
     }
 
@@ -26,18 +29,33 @@ impl Sprite {
         let priority_flag = value & 0x80 == 0x80;
         let y_flip_flag = value & 0x40 == 0x40;
         let x_flip_flag = value & 0x20 == 0x20;
-        let pallete_number = value & 0x10 as u8;
+        let pallete_number = (value & 0x10) >> 4;
         self.priority_flag = priority_flag;
         self.y_flip_flag = y_flip_flag;
         self.x_flip_flag = x_flip_flag;
         self.pallete_number = pallete_number;
+        self.cgb_flags = value & 0x0F;
+    }
+
+    pub fn read_flags(&self) -> u8 {
+        let mut value = self.cgb_flags | (self.pallete_number << 4);
+        if self.priority_flag {
+            value |= 0x80;
+        }
+        if self.y_flip_flag {
+            value |= 0x40;
+        }
+        if self.x_flip_flag {
+            value |= 0x20;
+        }
+        value
     }
 
     pub fn write_sprite_address(&mut self, address: usize, value: u8) {
         let address = address % 4;
         match address {
-            0 => self.x = value,
-            1 => self.y = value,
+            0 => self.y = value,
+            1 => self.x = value,
             2 => self.tile_number = value,
             3 => {
                 self.write_flags(value);
@@ -45,4 +63,40 @@ impl Sprite {
             _ => panic!("can't happen"),
         }
     }
+
+    pub fn read_sprite_address(&self, address: usize) -> u8 {
+        match address % 4 {
+            0 => self.y,
+            1 => self.x,
+            2 => self.tile_number,
+            3 => self.read_flags(),
+            _ => panic!("can't happen"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let mut sprite = Sprite::new();
+        sprite.write_flags(0xB5);
+        assert!(sprite.priority_flag);
+        assert!(!sprite.y_flip_flag);
+        assert!(sprite.x_flip_flag);
+        assert_eq!(sprite.pallete_number, 1);
+        assert_eq!(sprite.read_flags(), 0xB5);
+    }
+
+    #[test]
+    fn test_oam_byte_order() {
+        let mut sprite = Sprite::new();
+        sprite.write_sprite_address(0x04, 0x10);
+        sprite.write_sprite_address(0x05, 0x08);
+        assert_eq!(sprite.y, 0x10);
+        assert_eq!(sprite.x, 0x08);
+        assert_eq!(sprite.read_sprite_address(0x05), 0x08);
+    }
 }
@@ -0,0 +1,234 @@
+/// DIV, TIMA, TMA and TAC at 0xFF04..=0xFF07. Everything hangs off a 16-bit divider
+/// counting T-cycles, DIV being its upper byte and TIMA counting falling edges of the
+/// divider bit TAC selects.
+pub struct Timer {
+    divider: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // T-cycles not yet run, the timer moves a machine cycle at a time
+    cycles: u64,
+    // TIMA overflowed and reads 0 for a cycle before TMA is loaded
+    overflowed: bool,
+    // TMA was loaded on the last cycle, TIMA writes are lost until the next one
+    reloaded: bool,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            divider: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            cycles: 0,
+            overflowed: false,
+            reloaded: false,
+        }
+    }
+
+    pub fn set_divider(&mut self, divider: u16) {
+        self.divider = divider;
+    }
+
+    /// Runs the timer for `ticks` T-cycles, returning whether the Timer interrupt was requested.
+    pub fn tick(&mut self, ticks: u64) -> bool {
+        self.cycles += ticks;
+        let mut interrupt = false;
+        while self.cycles >= 4 {
+            self.cycles -= 4;
+            interrupt |= self.step();
+        }
+        interrupt
+    }
+
+    fn step(&mut self) -> bool {
+        self.reloaded = false;
+        let interrupt = if self.overflowed {
+            self.overflowed = false;
+            self.reloaded = true;
+            self.tima = self.tma;
+            true
+        } else {
+            false
+        };
+
+        let before = self.signal();
+        self.divider = self.divider.wrapping_add(4);
+        if before && !self.signal() {
+            self.increment();
+        }
+        interrupt
+    }
+
+    // the enable bit is ANDed with the divider bit, so both feed the edge detector
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0x00 => 9,
+            0x01 => 3,
+            0x02 => 5,
+            _ => 7,
+        };
+        self.tac & 0x04 != 0 && self.divider & (1 << bit) != 0
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflowed = overflow;
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            0xFF04 => (self.divider >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => panic!("invalid timer register, this must be a programming error"),
+        }
+    }
+
+    /// Sets a register without the edge detection a bus write goes through.
+    pub fn load(&mut self, address: usize, value: u8) {
+        match address {
+            0xFF04 => self.divider = 0,
+            0xFF05 => self.tima = value,
+            0xFF06 => self.tma = value,
+            0xFF07 => self.tac = value & 0x07,
+            _ => panic!("invalid timer register, this must be a programming error"),
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0xFF04 => {
+                // clearing the divider can drop the selected bit, which counts as an edge
+                let before = self.signal();
+                self.divider = 0;
+                if before {
+                    self.increment();
+                }
+            }
+            0xFF05 => {
+                if !self.reloaded {
+                    // writing during the overflow cycle cancels the reload and interrupt
+                    self.overflowed = false;
+                    self.tima = value;
+                }
+            }
+            0xFF06 => {
+                self.tma = value;
+                if self.reloaded {
+                    self.tima = value;
+                }
+            }
+            0xFF07 => {
+                // same again for switching frequency or disabling while the bit is high
+                let before = self.signal();
+                self.tac = value & 0x07;
+                if before && !self.signal() {
+                    self.increment();
+                }
+            }
+            _ => panic!("invalid timer register, this must be a programming error"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(tac: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, tac);
+        timer
+    }
+
+    #[test]
+    fn test_div_counts_upper_byte() {
+        let mut timer = Timer::new();
+        timer.tick(255);
+        assert_eq!(timer.read(0xFF04), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(0xFF04), 1);
+        timer.write(0xFF04, 0x69);
+        assert_eq!(timer.read(0xFF04), 0);
+    }
+
+    #[test]
+    fn test_tima_frequencies() {
+        for (tac, period) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            let mut timer = timer(tac);
+            timer.tick(period - 4);
+            assert_eq!(timer.read(0xFF05), 0, "tac {:02X}", tac);
+            timer.tick(4);
+            assert_eq!(timer.read(0xFF05), 1, "tac {:02X}", tac);
+        }
+    }
+
+    #[test]
+    fn test_disabled_timer_does_not_count() {
+        let mut timer = timer(0x01);
+        timer.tick(1024);
+        assert_eq!(timer.read(0xFF05), 0);
+    }
+
+    #[test]
+    fn test_overflow_reloads_a_cycle_later() {
+        let mut timer = timer(0x05);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF06, 0x80);
+
+        assert!(!timer.tick(16));
+        assert_eq!(timer.read(0xFF05), 0x00);
+        assert!(timer.tick(4));
+        assert_eq!(timer.read(0xFF05), 0x80);
+    }
+
+    #[test]
+    fn test_tima_write_cancels_overflow() {
+        let mut timer = timer(0x05);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF06, 0x80);
+        timer.tick(16);
+        timer.write(0xFF05, 0x10);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read(0xFF05), 0x10);
+    }
+
+    #[test]
+    fn test_tima_write_lost_during_reload() {
+        let mut timer = timer(0x05);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF06, 0x80);
+        timer.tick(20);
+        timer.write(0xFF05, 0x10);
+        assert_eq!(timer.read(0xFF05), 0x80);
+        timer.write(0xFF06, 0x20);
+        assert_eq!(timer.read(0xFF05), 0x20);
+    }
+
+    #[test]
+    fn test_div_write_glitch() {
+        let mut timer = timer(0x05);
+        // bit 3 is set, resetting the divider is a falling edge
+        timer.tick(8);
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 1);
+
+        // but not when it's clear
+        timer.tick(4);
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 1);
+    }
+
+    #[test]
+    fn test_tac_write_glitch() {
+        let mut timer = timer(0x05);
+        timer.tick(8);
+        timer.write(0xFF07, 0x01);
+        assert_eq!(timer.read(0xFF05), 1);
+        assert_eq!(timer.read(0xFF07), 0xF9);
+    }
+}
@@ -0,0 +1,17 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Writes `data` next to `path` first and renames it into place, so a crash
+/// mid-write never leaves a truncated file behind.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, path)
+}
@@ -0,0 +1,262 @@
+// A DEFLATE (RFC 1951) decoder, enough to pull ROMs out of zip and gzip files.
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// the order code length code lengths are sent in for dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> Result<u32, String> {
+        let mut value = 0u32;
+        for i in 0..count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or("deflate stream ends early")?;
+            value |= (((byte >> self.bit) & 0x01) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.position += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.position += 1;
+        }
+    }
+}
+
+// canonical Huffman code, symbols grouped by code length
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code in deflate stream".to_string())
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_table = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_table.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or("repeat with no previous length")?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() != literal_count + distance_count {
+        return Err("code lengths overrun in deflate stream".to_string());
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let length =
+                    LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index])? as usize;
+                let index = distances.decode(reader)? as usize;
+                if index >= 30 {
+                    return Err("invalid distance in deflate stream".to_string());
+                }
+                let distance =
+                    DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index])? as usize;
+                if distance > output.len() {
+                    return Err("distance too far back in deflate stream".to_string());
+                }
+                // copied a byte at a time since the source can overlap what's being written
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+            _ => return Err("invalid literal in deflate stream".to_string()),
+        }
+    }
+}
+
+/// Decompresses a raw DEFLATE stream, returning the data and how many input bytes it used.
+pub fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut reader = BitReader {
+        data,
+        position: 0,
+        bit: 0,
+    };
+    let mut output = Vec::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = data
+                    .get(reader.position..reader.position + 4)
+                    .ok_or("deflate stream ends early")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let complement = u16::from_le_bytes([header[2], header[3]]) as usize;
+                if length != !complement & 0xFFFF {
+                    return Err("stored block length mismatch in deflate stream".to_string());
+                }
+                let start = reader.position + 4;
+                let block = data
+                    .get(start..start + length)
+                    .ok_or("deflate stream ends early")?;
+                output.extend_from_slice(block);
+                reader.position = start + length;
+            }
+            1 => {
+                let (literals, distances) = fixed_tables();
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut output, &literals, &distances)?;
+            }
+            _ => return Err("invalid block type in deflate stream".to_string()),
+        }
+
+        if last {
+            reader.align();
+            return Ok((output, reader.position));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_block() {
+        let data = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+        assert_eq!(inflate(&data).unwrap(), (b"abc".to_vec(), 8));
+    }
+
+    #[test]
+    fn test_fixed_block_with_back_reference() {
+        // "abcabcabcabc" as compressed by zlib
+        let data = [0x4B, 0x4C, 0x4A, 0x4E, 0x84, 0x21, 0x00];
+        assert_eq!(inflate(&data).unwrap().0, b"abcabcabcabc".to_vec());
+    }
+
+    #[test]
+    fn test_dynamic_block() {
+        // zlib picks a dynamic block for this one
+        let data = [
+            0x25, 0x85, 0x01, 0x0D, 0x00, 0x00, 0x08, 0x83, 0xB2, 0x82, 0xFD, 0x3B, 0xF8, 0x2B,
+            0x1B, 0x80, 0x84, 0x41, 0xB1, 0xE1, 0x0E, 0x67, 0xD1, 0x6F, 0xBE,
+        ];
+        let expected = b"abaaaacabbababbaabababaaabaaaaaaabbaaaabbbba";
+        assert_eq!(inflate(&data).unwrap(), (expected.to_vec(), data.len()));
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        assert!(inflate(&[0x4B, 0x4C]).is_err());
+    }
+}
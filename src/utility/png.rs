@@ -0,0 +1,95 @@
+use crate::utility::checksum::{adler32, crc32};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// the most a stored deflate block can hold
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+/// Encodes 8-bit RGB pixels, three bytes each in rows of `width`, as a PNG. The image
+/// data goes into uncompressed deflate blocks, which keeps this short at the cost of size.
+pub fn encode(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(
+        rgb.len(),
+        width as usize * height as usize * 3,
+        "pixel data doesn't match the image size, this must be a programming error"
+    );
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits a channel, RGB, deflate, no filtering scheme, not interlaced
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // every row starts with its filter type, 0 for none
+    let mut scanlines = Vec::with_capacity(rgb.len() + height as usize);
+    for row in rgb.chunks(width as usize * 3) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window and no preset dictionary
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        stream.push(last as u8);
+        let length = block.len() as u16;
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::inflate::inflate;
+
+    #[test]
+    fn test_encode() {
+        let rgb: Vec<u8> = (0..4 * 3 * 3).map(|i| i as u8).collect();
+        let png = encode(4, 3, &rgb);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 4, 0, 0, 0, 3]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        let idat_length = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let zlib = &png[41..41 + idat_length];
+        let (scanlines, _) = inflate(&zlib[2..]).unwrap();
+        assert_eq!(scanlines.len(), 3 * 13);
+        assert_eq!(scanlines[0], 0);
+        assert_eq!(scanlines[1..13], rgb[..12]);
+        assert_eq!(zlib[zlib.len() - 4..], adler32(&scanlines).to_be_bytes());
+    }
+
+    #[test]
+    fn test_large_data_splits_into_blocks() {
+        let data = vec![0xAB; MAX_STORED_BLOCK + 10];
+        let stream = zlib_stored(&data);
+        let (output, _) = inflate(&stream[2..]).unwrap();
+        assert_eq!(output, data);
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by Ctrl-C or SIGTERM. The run loop stops at the next instruction and flushes the
+/// save and recording on the way out, which a plain kill would skip.
+pub static QUIT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+pub fn install_handler() {
+    const SIGINT: i32 = 2;
+    const SIGTERM: i32 = 15;
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    // only an atomic store, nothing else is safe in a signal handler
+    extern "C" fn handle(_signal: i32) {
+        QUIT.store(true, Ordering::SeqCst);
+    }
+
+    unsafe {
+        signal(SIGINT, handle);
+        signal(SIGTERM, handle);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_handler() {}
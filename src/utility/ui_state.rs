@@ -7,6 +7,11 @@ pub struct UIState {
     left: bool,
     start: bool,
     select: bool,
+    // in g, for carts with an accelerometer, the front-end maps keys or the mouse to it
+    tilt_x: f32,
+    tilt_y: f32,
+    // whether the host sees infrared light, for carts with an IR port
+    infrared_light: bool,
 }
 
 impl UIState {
@@ -20,9 +25,20 @@ impl UIState {
             left: false,
             start: false,
             select: false,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            infrared_light: false,
         }
     }
 
+    pub fn tilt(&self) -> (f32, f32) {
+        (self.tilt_x, self.tilt_y)
+    }
+
+    pub fn infrared_light(&self) -> bool {
+        self.infrared_light
+    }
+
     pub fn has_negative_edge(from_state: &UIState, to_state: &UIState) -> bool {
         (!from_state.a && to_state.a)
             || (!from_state.b && to_state.b)